@@ -6,6 +6,9 @@ pub mod element;
 pub mod id;
 mod inspector;
 pub mod io;
+pub mod reaction;
+pub mod rule;
+pub mod transition;
 mod value;
 
 pub struct AtomPhysicsPlugin;
@@ -1,13 +1,57 @@
 use bevy::prelude::Resource;
 
-use crate::terrain::{color::AtomColor, Atom, JoinFace};
+use crate::terrain::{color::AtomColor, storage::AMBIENT_TEMPERATURE, Atom, JoinFace};
 
-use super::id::{CreateInstanceWithId, IdMap, MappedToId};
+use super::{
+    id::{CreateInstanceWithId, IdMap, MappedToId},
+    rule::Rule,
+    transition::Transitions,
+};
 
 #[derive(Debug, Clone)]
 pub struct Element {
     pub color: AtomColor,
     pub join_face: JoinFace,
+    pub state: State,
+    /// Movable atoms sink through lighter ones and rise through heavier ones.
+    pub density: f32,
+    /// Fraction of the temperature difference with each neighbour that is
+    /// evened out per tick, between 0 and 1.
+    pub conductivity: f32,
+    /// Temperature new atoms of this element start at, in °C.
+    pub default_temperature: f32,
+    /// Checked in order each tick; only the first that applies is used.
+    pub rules: Vec<Rule>,
+    pub transitions: Transitions,
+}
+
+/// Built in motion for atoms of an element, applied each tick if none of the
+/// element's rules apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Never moves.
+    Solid,
+    /// Falls, and slides diagonally down to form piles.
+    Powder,
+    /// Falls, and spreads out horizontally.
+    Liquid,
+    /// Rises, and spreads out horizontally.
+    Gas,
+}
+
+impl State {
+    /// Density used when an element doesn't set one, in kg/m³.
+    pub const fn default_density(self) -> f32 {
+        match self {
+            State::Solid | State::Powder | State::Liquid => 1000.0,
+            State::Gas => 0.5,
+        }
+    }
+
+    /// Whether other atoms can move through atoms in this state.
+    pub const fn is_movable(self) -> bool {
+        !matches!(self, State::Solid)
+    }
 }
 
 pub type ElementId = u8;
@@ -17,6 +61,12 @@ impl Default for Element {
         Self {
             color: AtomColor::WHITE,
             join_face: JoinFace::SameAlpha,
+            state: State::Solid,
+            density: State::Solid.default_density(),
+            conductivity: 0.5,
+            default_temperature: AMBIENT_TEMPERATURE,
+            rules: Vec::new(),
+            transitions: Transitions::default(),
         }
     }
 }
@@ -38,6 +88,9 @@ impl MappedToId for Element {
             "Air",
             Element {
                 color: AtomColor::INVISIBLE,
+                state: State::Gas,
+                density: 1.2,
+                conductivity: 0.05,
                 ..Default::default()
             },
         );
@@ -61,7 +114,7 @@ impl Resource for IdMap<Element> {}
 
 impl Element {
     pub const VOID_ID: <Self as MappedToId>::Id = 0;
-    pub const AIR_ID: <Self as MappedToId>::Id = 0;
+    pub const AIR_ID: <Self as MappedToId>::Id = 1;
 }
 
 impl IdMap<Element> {
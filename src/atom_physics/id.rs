@@ -30,6 +30,12 @@ impl<T: MappedToId> IdMap<T> {
         self.0.get_index(index.to_usize()).map(|(_, value)| value)
     }
 
+    pub fn get_mut(&mut self, index: T::Id) -> Option<&mut T> {
+        self.0
+            .get_index_mut(index.to_usize())
+            .map(|(_, value)| value)
+    }
+
     pub fn get_full(&self, index: T::Id) -> Option<(&str, &T)> {
         self.0
             .get_index(index.to_usize())
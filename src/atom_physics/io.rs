@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     fs::{self, File},
     io::{self, Read},
     path::PathBuf,
@@ -8,13 +9,13 @@ use bevy::prelude::*;
 
 use crate::terrain::{thread::TerrainThread, AtomWorld};
 
-use self::diagnostics::{Diagnostic, Diagnostics};
-
-use super::{
-    element::Element,
-    id::{IdMap, MappedToId},
+use self::{
+    diagnostics::{Diagnostic, Diagnostics},
+    parsing::{imported_sets, ParsedSet, SetBuilder},
 };
 
+use super::id::{IdMap, MappedToId};
+
 mod diagnostics;
 mod parsing;
 
@@ -95,26 +96,67 @@ fn load_avalible_sets(avalible_sets: &mut Vec<SetHandle>) -> io::Result<()> {
 
 pub fn load_and_reload_set(set: SetHandle, world: &mut AtomWorld) {
     let mut diagnostics = Diagnostics::init();
-    if let Some(new_elements) = load_set(&set, &mut diagnostics) {
+    if let Some(new_set) = load_set(&set, &mut diagnostics) {
         if !diagnostics.has_errored() {
-            hot_reload_set(world, new_elements);
+            hot_reload_set(world, new_set);
         }
     }
 }
 
-fn load_set(set: &SetHandle, diagnostics: &mut Diagnostics) -> Option<IdMap<Element>> {
-    let files = read_files(set, diagnostics);
+fn load_set(set: &SetHandle, diagnostics: &mut Diagnostics) -> Option<ParsedSet> {
+    let mut files = FileContents::create_map();
+    let sets = read_sets(set, &mut files, diagnostics);
     let ret = (!diagnostics.has_errored()).then(|| {
-        let mut elements = Element::create_map();
-        for (id, _name, FileContents(file)) in files.iter() {
-            parsing::parse_file(file, id, diagnostics, &mut elements);
+        let mut builder = SetBuilder::named(&set.name);
+        for (name, ids) in &sets {
+            for &id in ids {
+                let FileContents(file) = &files[id];
+                if *name == set.name {
+                    builder.parse_file(file, id, diagnostics);
+                } else {
+                    builder.add_dependency_file(name, file, id);
+                }
+            }
         }
-        elements
+        builder.finish(diagnostics)
     });
     diagnostics.print_to_console(&files);
     ret
 }
 
+/// Reads the files of `set` and of the sets it imports, directly or through
+/// other sets.  Returns the name of each set read and the ids of its files.
+fn read_sets(
+    set: &SetHandle,
+    files: &mut IdMap<FileContents>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<(String, Vec<FileId>)> {
+    let mut avalible_sets = Vec::new();
+    if let Err(e) = load_avalible_sets(&mut avalible_sets) {
+        diagnostics.add_unpositioned(ReadFilesError::ListSets(e));
+    }
+
+    let mut sets = Vec::new();
+    let mut seen = HashSet::from([set.name.clone()]);
+    let mut to_read = vec![set.clone()];
+    while let Some(set) = to_read.pop() {
+        let ids = read_files(&set, files, diagnostics);
+        for &id in &ids {
+            let FileContents(file) = &files[id];
+            for import in imported_sets(file, id) {
+                // Sets that don't exist are reported when the set is built.
+                if let Some(dependency) = avalible_sets.iter().find(|s| s.name == import) {
+                    if seen.insert(dependency.name.clone()) {
+                        to_read.push(dependency.clone());
+                    }
+                }
+            }
+        }
+        sets.push((set.name, ids));
+    }
+    sets
+}
+
 #[derive(Debug, Clone)]
 struct FileContents(String);
 
@@ -126,6 +168,7 @@ type FileId = u16;
 
 #[derive(Debug)]
 pub enum ReadFilesError {
+    ListSets(std::io::Error),
     ReadDirectory(std::io::Error),
     OpenFile { name: String, e: std::io::Error },
     ReadFile { name: String, e: std::io::Error },
@@ -134,15 +177,18 @@ pub enum ReadFilesError {
 impl Diagnostic for ReadFilesError {
     fn level(&self) -> diagnostics::Level {
         match self {
-            ReadFilesError::OpenFile { .. } | ReadFilesError::ReadFile { .. } => {
-                diagnostics::Level::Warn
-            }
+            ReadFilesError::ListSets(_)
+            | ReadFilesError::OpenFile { .. }
+            | ReadFilesError::ReadFile { .. } => diagnostics::Level::Warn,
             ReadFilesError::ReadDirectory(_) => diagnostics::Level::Error,
         }
     }
 
     fn description(&self) -> String {
         match self {
+            ReadFilesError::ListSets(e) => {
+                format!("Unable to list sets to import from: {e}")
+            }
             ReadFilesError::ReadDirectory(e) => {
                 format!("Unable to read set directiory: {e}")
             }
@@ -156,15 +202,21 @@ impl Diagnostic for ReadFilesError {
     }
 }
 
+/// Reads the files of `set` into `files`, named after the set and the file,
+/// and returns their ids.
 #[must_use]
-fn read_files(set: &SetHandle, diagnostics: &mut Diagnostics) -> IdMap<FileContents> {
-    let mut files = FileContents::create_map();
+fn read_files(
+    set: &SetHandle,
+    files: &mut IdMap<FileContents>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<FileId> {
+    let mut ids = Vec::new();
 
     let entries = match fs::read_dir(&set.path) {
         Ok(entries) => entries,
         Err(e) => {
             diagnostics.add_unpositioned(ReadFilesError::ReadDirectory(e));
-            return files;
+            return ids;
         }
     };
     for entry in entries.filter_map(|e| e.ok()) {
@@ -173,7 +225,7 @@ fn read_files(set: &SetHandle, diagnostics: &mut Diagnostics) -> IdMap<FileConte
             && path.extension().and_then(|s| s.to_str()) == Some("splang")
         {
             let file_name = entry.file_name();
-            let file_name = file_name.to_string_lossy().into_owned();
+            let file_name = format!("{}/{}", set.name, file_name.to_string_lossy());
             let mut file = match File::open(path) {
                 Ok(file) => file,
                 Err(e) => {
@@ -185,9 +237,10 @@ fn read_files(set: &SetHandle, diagnostics: &mut Diagnostics) -> IdMap<FileConte
             let mut buf = String::new();
             match file.read_to_string(&mut buf) {
                 Ok(_) => {
-                    files
+                    let id = files
                         .insert(file_name, FileContents(buf))
                         .expect("Files can't have the same name");
+                    ids.push(id);
                 }
                 Err(e) => {
                     diagnostics.add_unpositioned(ReadFilesError::ReadFile { name: file_name, e });
@@ -197,10 +250,14 @@ fn read_files(set: &SetHandle, diagnostics: &mut Diagnostics) -> IdMap<FileConte
         }
     }
 
-    files
+    ids
 }
 
-fn hot_reload_set(world: &mut AtomWorld, new_elements: IdMap<Element>) {
+fn hot_reload_set(world: &mut AtomWorld, new_set: ParsedSet) {
+    let ParsedSet {
+        elements: new_elements,
+        reactions,
+    } = new_set;
     world.atoms.modify_all(|mut atom| {
         if let Some((name, old_element)) = world.elements.get_full(atom.element) {
             if let Some((id, element)) = new_elements.get_full_by_name(name) {
@@ -225,4 +282,8 @@ fn hot_reload_set(world: &mut AtomWorld, new_elements: IdMap<Element>) {
         }
     });
     world.elements = new_elements;
+    world.reactions = reactions;
+    // Elements may behave differently now, so even unchanged atoms need to be
+    // simulated again.
+    world.atoms.wake_all();
 }
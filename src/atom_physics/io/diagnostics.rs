@@ -8,6 +8,9 @@ use super::FileContents;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Level {
+    /// Extra context for the diagnostic before it, such as where something
+    /// was first defined.
+    Note,
     #[default]
     Warn,
     Error,
@@ -16,6 +19,7 @@ pub enum Level {
 impl std::fmt::Display for Level {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Level::Note => write!(f, "Note"),
             Level::Warn => write!(f, "Warning"),
             Level::Error => write!(f, "Error"),
         }
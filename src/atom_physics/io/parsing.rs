@@ -1,8 +1,15 @@
+use std::collections::HashMap;
+
+use bevy::prelude::IVec3;
+
 use crate::{
     atom_physics::{
-        element::Element,
-        id::{IdMap, InsertError},
-        value::ValueUntyped,
+        element::{Element, ElementId, State},
+        id::{IdMap, InsertError, MappedToId},
+        reaction::{Reaction, Reactions},
+        rule::{self, Replacement, Rule},
+        transition::{PhaseTransition, Transitions},
+        value::{NumberUnit, ValueUntyped},
     },
     terrain::JoinFace,
 };
@@ -14,22 +21,128 @@ use super::{
 
 use smartstring::alias::String;
 
+pub use self::imports::imported_sets;
+
+use self::constants::Constants;
+
 mod ast_evaluation;
 mod ast_generation;
+mod constants;
+mod imports;
+mod inheritance;
 
 #[derive(Debug, Clone)]
 pub enum Ast<'a> {
     Block(Positioned<Vec<Ast<'a>>>),
     Ident(Positioned<&'a str>),
     HexColor(Positioned<&'a str>),
+    Int(Positioned<&'a str>),
+    /// A number with a decimal point or a unit, such as `0.5` or `20°C`.
+    Float {
+        value: Positioned<&'a str>,
+        unit: Option<Positioned<&'a str>>,
+    },
+    Bool(Positioned<bool>),
+    /// A quoted string.  Escapes are processed when it's evaluated, and the
+    /// position includes the quotes.
+    String(Positioned<&'a str>),
+    /// `element Name { ... }`, or `element Name : Parent { ... }` to start
+    /// from the properties and rules of another element.
     Element {
         name: Positioned<&'a str>,
+        parent: Option<Positioned<&'a str>>,
         body: Positioned<Vec<Ast<'a>>>,
     },
     VariableAssign {
         variable: Positioned<&'a str>,
         value: Box<Ast<'a>>,
     },
+    /// `const NAME = value`
+    Const {
+        name: Positioned<&'a str>,
+        value: Box<Ast<'a>>,
+    },
+    /// `import set`, or `import set { Element ... }` to import only some of
+    /// its elements.  The set can be any value, so a bad name is reported
+    /// when the set is built rather than failing the whole file.
+    Import {
+        set: Box<Ast<'a>>,
+        elements: Option<Positioned<Vec<Ast<'a>>>>,
+    },
+    Rule(Positioned<Vec<Ast<'a>>>),
+    Reaction(Positioned<Vec<Ast<'a>>>),
+    /// `value at temperature`
+    At {
+        value: Box<Ast<'a>>,
+        at: Box<Ast<'a>>,
+    },
+    /// `lhs op rhs`
+    Binary {
+        op: Positioned<BinaryOp>,
+        lhs: Box<Ast<'a>>,
+        rhs: Box<Ast<'a>>,
+    },
+    /// `op operand`
+    Unary {
+        op: Positioned<UnaryOp>,
+        operand: Box<Ast<'a>>,
+    },
+    /// `function(args, ...)`, where the position of `args` includes the
+    /// parentheses.
+    Call {
+        function: Positioned<&'a str>,
+        args: Positioned<Vec<Ast<'a>>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "not",
+        }
+    }
 }
 
 impl<'a> PartialEq for Ast<'a> {
@@ -38,18 +151,35 @@ impl<'a> PartialEq for Ast<'a> {
             (Ast::Ident(a), Ast::Ident(b)) => a.object == b.object,
             (Ast::Block(a), Ast::Block(b)) => a.object == b.object,
             (Ast::HexColor(a), Ast::HexColor(b)) => a.object == b.object,
+            (Ast::Int(a), Ast::Int(b)) => a.object == b.object,
+            (
+                Ast::Float {
+                    value: a_val,
+                    unit: a_unit,
+                },
+                Ast::Float {
+                    value: b_val,
+                    unit: b_unit,
+                },
+            ) => a_val == b_val && a_unit == b_unit,
+            (Ast::Bool(a), Ast::Bool(b)) => a.object == b.object,
+            (Ast::String(a), Ast::String(b)) => a.object == b.object,
             (
                 Ast::Element {
                     name: a_name,
+                    parent: a_parent,
                     body: a_body,
-                    ..
                 },
                 Ast::Element {
                     name: b_name,
+                    parent: b_parent,
                     body: b_body,
-                    ..
                 },
-            ) => a_name.object == b_name.object && a_body.object == b_body.object,
+            ) => {
+                a_name.object == b_name.object
+                    && a_parent.map(|p| p.object) == b_parent.map(|p| p.object)
+                    && a_body.object == b_body.object
+            }
             (
                 Ast::VariableAssign {
                     variable: a_var,
@@ -62,6 +192,70 @@ impl<'a> PartialEq for Ast<'a> {
                     ..
                 },
             ) => a_var.object == b_var.object && a_val == b_val,
+            (
+                Ast::Const {
+                    name: a_name,
+                    value: a_val,
+                },
+                Ast::Const {
+                    name: b_name,
+                    value: b_val,
+                },
+            ) => a_name == b_name && a_val == b_val,
+            (
+                Ast::Import {
+                    set: a_set,
+                    elements: a_elements,
+                },
+                Ast::Import {
+                    set: b_set,
+                    elements: b_elements,
+                },
+            ) => a_set == b_set && a_elements == b_elements,
+            (Ast::Rule(a), Ast::Rule(b)) => a.object == b.object,
+            (Ast::Reaction(a), Ast::Reaction(b)) => a.object == b.object,
+            (
+                Ast::At {
+                    value: a_val,
+                    at: a_at,
+                },
+                Ast::At {
+                    value: b_val,
+                    at: b_at,
+                },
+            ) => a_val == b_val && a_at == b_at,
+            (
+                Ast::Binary {
+                    op: a_op,
+                    lhs: a_lhs,
+                    rhs: a_rhs,
+                },
+                Ast::Binary {
+                    op: b_op,
+                    lhs: b_lhs,
+                    rhs: b_rhs,
+                },
+            ) => a_op == b_op && a_lhs == b_lhs && a_rhs == b_rhs,
+            (
+                Ast::Unary {
+                    op: a_op,
+                    operand: a_operand,
+                },
+                Ast::Unary {
+                    op: b_op,
+                    operand: b_operand,
+                },
+            ) => a_op == b_op && a_operand == b_operand,
+            (
+                Ast::Call {
+                    function: a_function,
+                    args: a_args,
+                },
+                Ast::Call {
+                    function: b_function,
+                    args: b_args,
+                },
+            ) => a_function == b_function && a_args == b_args,
             _ => false,
         }
     }
@@ -75,41 +269,182 @@ impl<'a> Ast<'a> {
             Ast::Block(b) => b.position,
             Ast::Ident(i) => i.position,
             Ast::HexColor(c) => c.position.extend_back_same_line(1),
-            Ast::Element { name, body } => name.position.extend_to(body.position),
+            Ast::Int(n) => n.position,
+            Ast::Float { value, unit } => match unit {
+                Some(unit) => value.position.extend_to(unit.position),
+                None => value.position,
+            },
+            Ast::Bool(b) => b.position,
+            Ast::String(s) => s.position,
+            Ast::Element { name, body, .. } => name.position.extend_to(body.position),
             Ast::VariableAssign { variable, value } => {
                 variable.position.extend_to(value.position())
             }
+            Ast::Const { name, value } => name.position.extend_to(value.position()),
+            Ast::Import { set, elements } => match elements {
+                Some(elements) => set.position().extend_to(elements.position),
+                None => set.position(),
+            },
+            Ast::Rule(r) | Ast::Reaction(r) => r.position,
+            Ast::At { value, at } => value.position().extend_to(at.position()),
+            Ast::Binary { lhs, rhs, .. } => lhs.position().extend_to(rhs.position()),
+            Ast::Unary { op, operand } => op.position.extend_to(operand.position()),
+            Ast::Call { function, args } => function.position.extend_to(args.position),
         }
     }
 }
 
-pub fn parse_file(
-    code: &str,
-    file: FileId,
-    diagnostics: &mut Diagnostics,
-    elements: &mut IdMap<Element>,
-) {
-    let asts = Ast::generate(code, file, diagnostics);
-    for ast in asts {
-        match ast {
-            Ast::Element { name, ref body } => {
-                let element = parse_element(body, diagnostics);
-                match elements.insert(*name, element) {
-                    Ok(_) => {}
-                    Err(InsertError::DuplicateName) => diagnostics.add(
-                        ast.position(),
-                        ElementError::DoubleDefineElement(name.object.into()),
-                    ),
-                    Err(InsertError::NoMoreIds) => {
-                        diagnostics.add(ast.position(), ElementError::ElementLimitReached)
-                    }
+/// The name of an element that has not been resolved to an id yet.
+pub type ElementName = Positioned<String>;
+
+/// The parts of an element that reference other elements by name.
+#[derive(Debug, Default, Clone)]
+pub struct ElementReferences {
+    pub rules: Vec<Rule<ElementName>>,
+    pub transitions: Transitions<ElementName>,
+}
+
+/// Collects the items in each file of a set, then parses them once every file
+/// has been read, so constants and elements can be used from any file.
+#[derive(Debug, Default)]
+pub struct SetBuilder<'a> {
+    /// The name of the set, so that sets importing it back can be reported.
+    name: Option<String>,
+    asts: Vec<Ast<'a>>,
+    /// The files of the sets that may be imported, by set name.
+    dependencies: HashMap<String, Vec<(FileId, &'a str)>>,
+}
+
+/// Everything defined by the files of a set.
+#[derive(Debug)]
+pub struct ParsedSet {
+    pub elements: IdMap<Element>,
+    pub reactions: Reactions,
+}
+
+impl<'a> SetBuilder<'a> {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn parse_file(&mut self, code: &'a str, file: FileId, diagnostics: &mut Diagnostics) {
+        self.asts.extend(Ast::generate(code, file, diagnostics));
+    }
+
+    /// Adds a file of another set, which is only parsed if the set is
+    /// imported.
+    pub fn add_dependency_file(&mut self, set: &str, code: &'a str, file: FileId) {
+        self.dependencies
+            .entry(set.into())
+            .or_default()
+            .push((file, code));
+    }
+
+    /// Brings in what the set imports, evaluates its constants, then parses
+    /// its elements, each after its parent, and its reactions, and resolves
+    /// the names used in them, reporting any that do not exist.
+    pub fn finish(self, diagnostics: &mut Diagnostics) -> ParsedSet {
+        let asts = imports::resolve(
+            self.name.as_deref(),
+            self.asts,
+            &self.dependencies,
+            diagnostics,
+        );
+        let constants = Constants::resolve(&asts, diagnostics);
+
+        let mut elements = Element::create_map();
+        let mut references = Vec::new();
+        for (name, position, parsed) in inheritance::parse_elements(&asts, &constants, diagnostics)
+        {
+            match elements.insert(*name, parsed.element) {
+                Ok(id) => references.push((id, parsed.references)),
+                Err(InsertError::DuplicateName) => diagnostics.add(
+                    position,
+                    ElementError::DoubleDefineElement(name.object.into()),
+                ),
+                Err(InsertError::NoMoreIds) => {
+                    diagnostics.add(position, ElementError::ElementLimitReached)
+                }
+            }
+        }
+
+        let mut unresolved_reactions = Vec::new();
+        for ast in &asts {
+            match ast {
+                // Already parsed.
+                Ast::Element { .. } => {}
+                // Already evaluated or brought in.
+                Ast::Const { .. } | Ast::Import { .. } => {}
+                Ast::Block(b) => diagnostics.add(b.position, ParseError::UnexpectedBlock),
+                Ast::Ident(i) | Ast::VariableAssign { variable: i, .. } => {
+                    diagnostics.add(i.position, ParseError::UnexpectedIdent)
                 }
+                Ast::HexColor(c) | Ast::Int(c) | Ast::String(c) => {
+                    diagnostics.add(c.position, ParseError::UnexpectedValue)
+                }
+                Ast::Rule(r) => diagnostics.add(r.position, ParseError::UnexpectedRule),
+                Ast::Reaction(body) => {
+                    unresolved_reactions.extend(parse_reaction(body, &constants, diagnostics))
+                }
+                Ast::Float { .. }
+                | Ast::Bool(_)
+                | Ast::At { .. }
+                | Ast::Binary { .. }
+                | Ast::Unary { .. }
+                | Ast::Call { .. } => diagnostics.add(ast.position(), ParseError::UnexpectedValue),
             }
-            Ast::Block(b) => diagnostics.add(b.position, ParseError::UnexpectedBlock),
-            Ast::Ident(i) | Ast::VariableAssign { variable: i, .. } => {
-                diagnostics.add(i.position, ParseError::UnexpectedIdent)
+        }
+
+        for (id, references) in references {
+            let rules = references
+                .rules
+                .into_iter()
+                .filter_map(|rule| {
+                    rule.resolve(|name| resolve_element(&elements, name, diagnostics))
+                })
+                .collect();
+            let transitions = references
+                .transitions
+                .resolve(|name| resolve_element(&elements, name, diagnostics));
+            let element = elements.get_mut(id).unwrap();
+            element.rules = rules;
+            element.transitions = transitions;
+        }
+
+        let mut reactions = Reactions::default();
+        for reaction in unresolved_reactions {
+            if let Some(reaction) =
+                reaction.resolve(|name| resolve_element(&elements, name, diagnostics))
+            {
+                reactions.insert(reaction);
             }
-            Ast::HexColor(c) => diagnostics.add(c.position, ParseError::UnexpectedValue),
+        }
+
+        ParsedSet {
+            elements,
+            reactions,
+        }
+    }
+}
+
+fn resolve_element(
+    elements: &IdMap<Element>,
+    name: ElementName,
+    diagnostics: &mut Diagnostics,
+) -> Option<ElementId> {
+    match elements.get_full_by_name(&name) {
+        Some((id, _)) => Some(id),
+        None => {
+            diagnostics.add(name.position, ElementError::UnknownElement(name.object));
+            None
         }
     }
 }
@@ -120,6 +455,7 @@ enum ParseError {
     UnexpectedBlock,
     UnexpectedIdent,
     UnexpectedValue,
+    UnexpectedRule,
 }
 
 impl Diagnostic for ParseError {
@@ -132,14 +468,47 @@ impl Diagnostic for ParseError {
             ParseError::UnexpectedBlock => "Unexpected block".to_owned(),
             ParseError::UnexpectedIdent => "Unexpected identifier".to_owned(),
             ParseError::UnexpectedValue => "Unexpected value".to_owned(),
+            ParseError::UnexpectedRule => "Rules must be inside an element".to_owned(),
         }
     }
 }
 
-pub fn parse_element(body: &[Ast<'_>], diagnostics: &mut Diagnostics) -> Element {
-    let mut element = Element::default();
+/// An element parsed from its definition, before the names of the elements it
+/// uses are resolved.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedElement {
+    pub element: Element,
+    pub references: ElementReferences,
+    /// Whether the density was given, by the element or one of its parents,
+    /// rather than following its state.
+    density_set: bool,
+}
+
+/// Parses the body of an element.  Properties not given in `body` are taken
+/// from `parent`, and the element's own rules are checked before the ones
+/// it inherits.
+pub fn parse_element(
+    body: &[Ast<'_>],
+    parent: Option<&ParsedElement>,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> ParsedElement {
+    let mut parsed = parent.cloned().unwrap_or_default();
+    let inherited_rules = std::mem::take(&mut parsed.references.rules);
+    let ParsedElement {
+        element,
+        references,
+        ..
+    } = &mut parsed;
     let mut color_set = false;
     let mut join_face_set = false;
+    let mut state_set = false;
+    let mut density_set = false;
+    let mut conductivity_set = false;
+    let mut default_temperature_set = false;
+    let mut melts_into_set = false;
+    let mut freezes_into_set = false;
+    let mut boils_into_set = false;
     for ast in body {
         match ast {
             Ast::VariableAssign { variable, value } => match **variable {
@@ -148,7 +517,7 @@ pub fn parse_element(body: &[Ast<'_>], diagnostics: &mut Diagnostics) -> Element
                         diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
                     }
                     color_set = true;
-                    match value.const_eval() {
+                    match value.const_eval(constants) {
                         Ok(ValueUntyped::Color(val)) => {
                             element.color = val;
                         }
@@ -167,7 +536,7 @@ pub fn parse_element(body: &[Ast<'_>], diagnostics: &mut Diagnostics) -> Element
                         diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
                     }
                     join_face_set = true;
-                    match value.const_eval() {
+                    match value.const_eval(constants) {
                         Ok(ValueUntyped::EnumVariant("Never")) => {
                             element.join_face = JoinFace::Never;
                         }
@@ -184,12 +553,392 @@ pub fn parse_element(body: &[Ast<'_>], diagnostics: &mut Diagnostics) -> Element
                         Err(e) => diagnostics.add_positioned(e),
                     }
                 }
+                "state" => {
+                    if state_set {
+                        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+                    }
+                    state_set = true;
+                    match value.const_eval(constants) {
+                        Ok(ValueUntyped::EnumVariant("Solid")) => {
+                            element.state = State::Solid;
+                        }
+                        Ok(ValueUntyped::EnumVariant("Powder")) => {
+                            element.state = State::Powder;
+                        }
+                        Ok(ValueUntyped::EnumVariant("Liquid")) => {
+                            element.state = State::Liquid;
+                        }
+                        Ok(ValueUntyped::EnumVariant("Gas")) => {
+                            element.state = State::Gas;
+                        }
+                        Ok(val) => diagnostics.add(
+                            value.position(),
+                            ElementError::VariableType {
+                                expected: "{ Solid | Powder | Liquid | Gas }".into(),
+                                found: val.variant_name(),
+                            },
+                        ),
+                        Err(e) => diagnostics.add_positioned(e),
+                    }
+                }
+                "density" => {
+                    if let Some(val) =
+                        parse_number(value, None, &mut density_set, constants, diagnostics)
+                    {
+                        element.density = val;
+                    }
+                }
+                "conductivity" => {
+                    let unit = Some(NumberUnit::Percent);
+                    if let Some(val) =
+                        parse_number(value, unit, &mut conductivity_set, constants, diagnostics)
+                    {
+                        element.conductivity = val.clamp(0.0, 1.0);
+                    }
+                }
+                "default_temperature" => {
+                    let unit = Some(NumberUnit::Celsius);
+                    if let Some(val) = parse_number(
+                        value,
+                        unit,
+                        &mut default_temperature_set,
+                        constants,
+                        diagnostics,
+                    ) {
+                        element.default_temperature = val;
+                    }
+                }
+                "melts_into" => parse_transition(
+                    value,
+                    &mut references.transitions.melts_into,
+                    &mut melts_into_set,
+                    constants,
+                    diagnostics,
+                ),
+                "freezes_into" => parse_transition(
+                    value,
+                    &mut references.transitions.freezes_into,
+                    &mut freezes_into_set,
+                    constants,
+                    diagnostics,
+                ),
+                "boils_into" => parse_transition(
+                    value,
+                    &mut references.transitions.boils_into,
+                    &mut boils_into_set,
+                    constants,
+                    diagnostics,
+                ),
                 _ => diagnostics.add(variable.position, ElementError::UnknownVariable),
             },
+            Ast::Rule(body) => references
+                .rules
+                .extend(parse_rule(body, constants, diagnostics)),
             _ => diagnostics.add(ast.position(), ElementError::UnexpectedAstKind),
         }
     }
-    element
+    references.rules.extend(inherited_rules);
+    parsed.density_set |= density_set;
+    if !parsed.density_set {
+        parsed.element.density = parsed.element.state.default_density();
+    }
+    parsed
+}
+
+/// Evaluates the value of a number property, reporting it if the property was
+/// already set or the value is not a number.  Numbers written in `unit` are
+/// accepted too.
+fn parse_number(
+    value: &Ast<'_>,
+    unit: Option<NumberUnit>,
+    set: &mut bool,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Option<f32> {
+    if *set {
+        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+    }
+    *set = true;
+    match value.const_eval(constants) {
+        Ok(val) => {
+            let number = val.as_number(unit);
+            if number.is_none() {
+                diagnostics.add(
+                    value.position(),
+                    ElementError::VariableType {
+                        expected: unit.map_or("Number", NumberUnit::quantity).into(),
+                        found: val.variant_name(),
+                    },
+                );
+            }
+            number
+        }
+        Err(e) => {
+            diagnostics.add_positioned(e);
+            None
+        }
+    }
+}
+
+/// Like [`parse_number`], but accepts percentages and clamps the value to
+/// between 0 and 1.
+fn parse_probability(
+    value: &Ast<'_>,
+    set: &mut bool,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Option<f32> {
+    let val = parse_number(
+        value,
+        Some(NumberUnit::Percent),
+        set,
+        constants,
+        diagnostics,
+    )?;
+    if !(0.0..=1.0).contains(&val) {
+        diagnostics.add(value.position(), ElementError::ProbabilityRange);
+    }
+    Some(val.clamp(0.0, 1.0))
+}
+
+/// Parses a `Element at temperature` phase transition.
+fn parse_transition(
+    value: &Ast<'_>,
+    transition: &mut Option<PhaseTransition<ElementName>>,
+    set: &mut bool,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) {
+    if *set {
+        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+    }
+    *set = true;
+    let Ast::At { value, at } = value else {
+        diagnostics.add(value.position(), ElementError::ExpectedTransition);
+        return;
+    };
+    let into = match value.const_eval(constants) {
+        Ok(ValueUntyped::EnumVariant(name)) => value.position().position(name.into()),
+        Ok(val) => {
+            diagnostics.add(
+                value.position(),
+                ElementError::VariableType {
+                    expected: "element".into(),
+                    found: val.variant_name(),
+                },
+            );
+            return;
+        }
+        Err(e) => {
+            diagnostics.add_positioned(e);
+            return;
+        }
+    };
+    if let Some(temperature) = parse_number(
+        at,
+        Some(NumberUnit::Celsius),
+        &mut false,
+        constants,
+        diagnostics,
+    ) {
+        *transition = Some(PhaseTransition { into, temperature });
+    }
+}
+
+fn parse_rule(
+    body: &Positioned<Vec<Ast<'_>>>,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Option<Rule<ElementName>> {
+    let mut pattern = None;
+    let mut replace = None;
+    let mut probability_set = false;
+    let mut probability = 1.0;
+    for ast in body.iter() {
+        match ast {
+            Ast::VariableAssign { variable, value } => match **variable {
+                "match" => {
+                    if pattern.is_some() {
+                        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+                    }
+                    pattern = Some(parse_neighbors(value, constants, diagnostics));
+                }
+                "replace" => {
+                    if replace.is_some() {
+                        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+                    }
+                    replace = Some(
+                        parse_neighbors(value, constants, diagnostics)
+                            .into_iter()
+                            .map(|(offset, name)| {
+                                let replacement = match rule::neighbor_offset(&name) {
+                                    Some(from) => Replacement::Atom(from),
+                                    None => Replacement::Element(name),
+                                };
+                                (offset, replacement)
+                            })
+                            .collect(),
+                    );
+                }
+                "probability" => {
+                    if let Some(val) =
+                        parse_probability(value, &mut probability_set, constants, diagnostics)
+                    {
+                        probability = val;
+                    }
+                }
+                _ => diagnostics.add(variable.position, ElementError::UnknownVariable),
+            },
+            _ => diagnostics.add(ast.position(), RuleError::UnexpectedAstKind),
+        }
+    }
+
+    match (pattern, replace) {
+        (Some(pattern), Some(replace)) => Some(Rule {
+            pattern,
+            replace,
+            probability,
+        }),
+        (None, _) => {
+            diagnostics.add(body.position, RuleError::MissingVariable("match"));
+            None
+        }
+        (_, None) => {
+            diagnostics.add(body.position, RuleError::MissingVariable("replace"));
+            None
+        }
+    }
+}
+
+fn parse_reaction(
+    body: &Positioned<Vec<Ast<'_>>>,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Option<Reaction<ElementName>> {
+    let mut reactants = None;
+    let mut products = None;
+    let mut probability_set = false;
+    let mut probability = 1.0;
+    for ast in body.iter() {
+        match ast {
+            Ast::VariableAssign { variable, value } => match **variable {
+                "reactants" => {
+                    if reactants.is_some() {
+                        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+                    }
+                    reactants = parse_element_pair(value, constants, diagnostics);
+                }
+                "products" => {
+                    if products.is_some() {
+                        diagnostics.add(value.position(), ElementError::DoubleDefineVariable);
+                    }
+                    products = parse_element_pair(value, constants, diagnostics);
+                }
+                "probability" => {
+                    if let Some(val) =
+                        parse_probability(value, &mut probability_set, constants, diagnostics)
+                    {
+                        probability = val;
+                    }
+                }
+                _ => diagnostics.add(variable.position, ElementError::UnknownVariable),
+            },
+            _ => diagnostics.add(ast.position(), ReactionError::UnexpectedAstKind),
+        }
+    }
+
+    match (reactants, products) {
+        (Some(reactants), Some(products)) => Some(Reaction {
+            reactants,
+            products,
+            probability,
+        }),
+        (None, _) => {
+            diagnostics.add(body.position, ReactionError::MissingVariable("reactants"));
+            None
+        }
+        (_, None) => {
+            diagnostics.add(body.position, ReactionError::MissingVariable("products"));
+            None
+        }
+    }
+}
+
+/// Parses a block of exactly two element names, such as `{ Fire Wood }`.
+fn parse_element_pair(
+    value: &Ast<'_>,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Option<[ElementName; 2]> {
+    let names = match value {
+        Ast::Block(block) if block.len() == 2 => {
+            block
+                .iter()
+                .filter_map(|ast| match ast.const_eval(constants) {
+                    Ok(ValueUntyped::EnumVariant(name)) => {
+                        Some(ast.position().position(name.into()))
+                    }
+                    Ok(val) => {
+                        diagnostics.add(
+                            ast.position(),
+                            ElementError::VariableType {
+                                expected: "element".into(),
+                                found: val.variant_name(),
+                            },
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        diagnostics.add_positioned(e);
+                        None
+                    }
+                })
+        }
+        _ => {
+            diagnostics.add(value.position(), ReactionError::ExpectedElementPair);
+            return None;
+        }
+    };
+    names.collect::<Vec<_>>().try_into().ok()
+}
+
+/// Parses a block of `neighbor = Name` assignments.
+fn parse_neighbors(
+    value: &Ast<'_>,
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<(IVec3, ElementName)> {
+    let Ast::Block(block) = value else {
+        diagnostics.add(value.position(), RuleError::ExpectedBlock);
+        return Vec::new();
+    };
+
+    let mut neighbors = Vec::new();
+    for ast in block.iter() {
+        match ast {
+            Ast::VariableAssign { variable, value } => {
+                let Some(offset) = rule::neighbor_offset(variable) else {
+                    diagnostics.add(variable.position, RuleError::UnknownNeighbor);
+                    continue;
+                };
+                match value.const_eval(constants) {
+                    Ok(ValueUntyped::EnumVariant(name)) => {
+                        neighbors.push((offset, value.position().position(name.into())))
+                    }
+                    Ok(val) => diagnostics.add(
+                        value.position(),
+                        ElementError::VariableType {
+                            expected: "element".into(),
+                            found: val.variant_name(),
+                        },
+                    ),
+                    Err(e) => diagnostics.add_positioned(e),
+                }
+            }
+            _ => diagnostics.add(ast.position(), RuleError::ExpectedBlock),
+        }
+    }
+    neighbors
 }
 
 #[derive(Debug, Clone)]
@@ -200,6 +949,9 @@ enum ElementError {
     UnknownVariable,
     DoubleDefineElement(String),
     ElementLimitReached,
+    UnknownElement(String),
+    ExpectedTransition,
+    ProbabilityRange,
 }
 
 impl Diagnostic for ElementError {
@@ -208,10 +960,12 @@ impl Diagnostic for ElementError {
             ElementError::DoubleDefineVariable
             | ElementError::UnknownVariable
             | ElementError::DoubleDefineElement(_)
-            | ElementError::ElementLimitReached => diagnostics::Level::Warn,
-            ElementError::UnexpectedAstKind | ElementError::VariableType { .. } => {
-                diagnostics::Level::Error
-            }
+            | ElementError::ElementLimitReached
+            | ElementError::ProbabilityRange => diagnostics::Level::Warn,
+            ElementError::UnexpectedAstKind
+            | ElementError::VariableType { .. }
+            | ElementError::UnknownElement(_)
+            | ElementError::ExpectedTransition => diagnostics::Level::Error,
         }
     }
 
@@ -225,13 +979,442 @@ impl Diagnostic for ElementError {
             }
             ElementError::DoubleDefineVariable => "Variable defined twice".to_owned(),
             ElementError::UnknownVariable => "Unknown variable".to_owned(),
-            ElementError::DoubleDefineElement(_) => {
-                "Element defined twice; using second definition".to_owned()
+            ElementError::DoubleDefineElement(name) => {
+                format!("Element {name} defined twice; using first definition")
             }
             ElementError::ElementLimitReached => format!(
                 "Limit of {} elements exceeded",
                 crate::atom_physics::element::ElementId::MAX
             ),
+            ElementError::UnknownElement(name) => format!("No element named {name} in this set"),
+            ElementError::ExpectedTransition => {
+                "Expected an element and temperature, such as `Water at 0`".to_owned()
+            }
+            ElementError::ProbabilityRange => {
+                "Probability should be between 0 and 1; clamping it".to_owned()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum RuleError {
+    UnexpectedAstKind,
+    ExpectedBlock,
+    UnknownNeighbor,
+    MissingVariable(&'static str),
+}
+
+impl Diagnostic for RuleError {
+    fn level(&self) -> diagnostics::Level {
+        diagnostics::Level::Error
+    }
+
+    fn description(&self) -> std::string::String {
+        match self {
+            RuleError::UnexpectedAstKind => {
+                "Rule body should contain only `match` and `replace`".to_owned()
+            }
+            RuleError::ExpectedBlock => {
+                "Expected a block of `neighbor = Element` assignments".to_owned()
+            }
+            RuleError::UnknownNeighbor => {
+                "Unknown neighbor; expected `self` or directions like `below` or `below_east`"
+                    .to_owned()
+            }
+            RuleError::MissingVariable(name) => format!("Rule is missing `{name}`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ReactionError {
+    UnexpectedAstKind,
+    ExpectedElementPair,
+    MissingVariable(&'static str),
+}
+
+impl Diagnostic for ReactionError {
+    fn level(&self) -> diagnostics::Level {
+        diagnostics::Level::Error
+    }
+
+    fn description(&self) -> std::string::String {
+        match self {
+            ReactionError::UnexpectedAstKind => {
+                "Reaction body should contain only `reactants`, `products`, and `probability`"
+                    .to_owned()
+            }
+            ReactionError::ExpectedElementPair => {
+                "Expected a block of two elements, such as `{ Fire Wood }`".to_owned()
+            }
+            ReactionError::MissingVariable(name) => format!("Reaction is missing `{name}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::color::AtomColor;
+
+    use super::*;
+
+    fn build_set(code: &str) -> (ParsedSet, Diagnostics) {
+        let mut diagnostics = Diagnostics::init();
+        let mut builder = SetBuilder::new();
+        builder.parse_file(code, 0, &mut diagnostics);
+        (builder.finish(&mut diagnostics), diagnostics)
+    }
+
+    fn build(code: &str) -> (IdMap<Element>, Diagnostics) {
+        let (set, diagnostics) = build_set(code);
+        (set.elements, diagnostics)
+    }
+
+    #[test]
+    fn rule_resolves_elements() {
+        let (elements, diagnostics) = build(
+            "\
+element Sand {
+    rule {
+        match = { below = Water }
+        replace = { self = Water below = self }
+        probability = 0.5
+    }
+}
+element Water {}",
+        );
+        assert!(diagnostics.is_empty());
+
+        let (sand, _) = elements.get_full_by_name("Sand").unwrap();
+        let (water, _) = elements.get_full_by_name("Water").unwrap();
+        assert_eq!(
+            elements[sand].rules,
+            [Rule {
+                pattern: vec![(IVec3::NEG_Y, water)],
+                replace: vec![
+                    (IVec3::ZERO, Replacement::Element(water)),
+                    (IVec3::NEG_Y, Replacement::Atom(IVec3::ZERO)),
+                ],
+                probability: 0.5,
+            }]
+        );
+    }
+
+    #[test]
+    fn rule_unknown_element() {
+        let (elements, diagnostics) = build(
+            "\
+element Sand {
+    rule {
+        match = { below = Lava }
+        replace = { self = below below = self }
+    }
+}",
+        );
+        assert!(diagnostics.has_errored());
+        let (sand, _) = elements.get_full_by_name("Sand").unwrap();
+        assert!(elements[sand].rules.is_empty());
+    }
+
+    #[test]
+    fn transitions_resolve_across_files() {
+        let mut diagnostics = Diagnostics::init();
+        let mut builder = SetBuilder::new();
+        builder.parse_file(
+            "element Ice { melts_into = Water at 0 }",
+            0,
+            &mut diagnostics,
+        );
+        builder.parse_file(
+            "element Water { freezes_into = Ice at 0 boils_into = Air at 100 }",
+            1,
+            &mut diagnostics,
+        );
+        let elements = builder.finish(&mut diagnostics).elements;
+        assert!(diagnostics.is_empty());
+
+        let (ice, _) = elements.get_full_by_name("Ice").unwrap();
+        let (water, _) = elements.get_full_by_name("Water").unwrap();
+        assert_eq!(
+            elements[ice].transitions.melts_into,
+            Some(PhaseTransition {
+                into: water,
+                temperature: 0.0
+            })
+        );
+        assert_eq!(elements[water].transitions.at(-5.0), Some(ice));
+        assert_eq!(elements[water].transitions.at(20.0), None);
+        assert_eq!(elements[water].transitions.at(120.0), Some(Element::AIR_ID));
+    }
+
+    #[test]
+    fn transition_unknown_element() {
+        let (elements, diagnostics) = build("element Ice { melts_into = Water at 0 }");
+        assert!(diagnostics.has_errored());
+        let (ice, _) = elements.get_full_by_name("Ice").unwrap();
+        assert_eq!(elements[ice].transitions.melts_into, None);
+    }
+
+    #[test]
+    fn reaction() {
+        let (set, diagnostics) = build_set(
+            "\
+element Fire {}
+element Wood {}
+reaction {
+    reactants = { Fire Wood }
+    products = { Fire Fire }
+    probability = 0.25
+}",
+        );
+        assert!(diagnostics.is_empty());
+        let (fire, _) = set.elements.get_full_by_name("Fire").unwrap();
+        let (wood, _) = set.elements.get_full_by_name("Wood").unwrap();
+        assert_eq!(
+            set.reactions.of(fire),
+            [Reaction {
+                reactants: [fire, wood],
+                products: [fire, fire],
+                probability: 0.25,
+            }]
+        );
+        assert!(set.reactions.of(wood).is_empty());
+    }
+
+    #[test]
+    fn reaction_errors() {
+        let (set, diagnostics) = build_set(
+            "\
+element Fire {}
+reaction {
+    reactants = { Fire Wood }
+    products = { Fire }
+}",
+        );
+        assert!(diagnostics.has_errored());
+        let (fire, _) = set.elements.get_full_by_name("Fire").unwrap();
+        assert!(set.reactions.of(fire).is_empty());
+    }
+
+    #[test]
+    fn literals_with_units() {
+        let (elements, diagnostics) = build(
+            "\
+element Steam {
+    default_temperature = 100°C
+    conductivity = 10%
+    rule {
+        match = { above = Air }
+        replace = { self = above above = self }
+        probability = 25%
+    }
+}",
+        );
+        assert!(diagnostics.is_empty());
+        let (_, steam) = elements.get_full_by_name("Steam").unwrap();
+        assert_eq!(steam.default_temperature, 100.0);
+        assert_eq!(steam.conductivity, 0.1);
+        assert_eq!(steam.rules[0].probability, 0.25);
+    }
+
+    #[test]
+    fn literal_type_errors() {
+        for body in [
+            "color = \"red\"",
+            "density = true",
+            "density = 50%",
+            "default_temperature = 20°F",
+            "conductivity = 20°C",
+            "color = \"bad \\q escape\"",
+        ] {
+            let (_, diagnostics) = build(&format!("element Thing {{ {body} }}"));
+            assert!(diagnostics.has_errored(), "{body} should be an error");
+        }
+    }
+
+    #[test]
+    fn constants_across_files() {
+        let mut diagnostics = Diagnostics::init();
+        let mut builder = SetBuilder::new();
+        builder.parse_file(
+            "element Stone { color = STONE_GREY melts_into = Lava at MELTING }",
+            0,
+            &mut diagnostics,
+        );
+        builder.parse_file(
+            "\
+const STONE_GREY = darken(BASE_GREY, 20%)
+const BASE_GREY = #686868
+const MELTING = 1200°C
+element Lava {}",
+            1,
+            &mut diagnostics,
+        );
+        let elements = builder.finish(&mut diagnostics).elements;
+        assert!(diagnostics.is_empty());
+
+        let (_, stone) = elements.get_full_by_name("Stone").unwrap();
+        assert_eq!(stone.color, AtomColor::from_parts(0x53, 0x53, 0x53, 0xff));
+        assert_eq!(stone.transitions.melts_into.unwrap().temperature, 1200.0);
+    }
+
+    #[test]
+    fn constant_errors() {
+        for code in [
+            "const A = B + 1",
+            "const A = B\nconst B = A",
+            "const A = A + 1",
+            "const A = 1 / 0\nelement Thing { density = A }",
+        ] {
+            let (_, diagnostics) = build(code);
+            assert!(diagnostics.has_errored(), "{code} should be an error");
         }
+
+        let (elements, diagnostics) =
+            build("const A = 1\nconst A = 2\nelement Thing { density = A }");
+        assert!(!diagnostics.has_errored() && !diagnostics.is_empty());
+        let (_, thing) = elements.get_full_by_name("Thing").unwrap();
+        assert_eq!(thing.density, 1.0);
+    }
+
+    #[test]
+    fn inheritance() {
+        let (elements, diagnostics) = build(
+            "\
+element Granite : Stone {
+    color = #8a7f80
+    rule {
+        match = { below = Air }
+        replace = { self = Air below = self }
+    }
+}
+element Stone : Rock {
+    state = Powder
+    melts_into = Lava at 1200°C
+    rule {
+        match = { above = Lava }
+        replace = { self = Lava }
+    }
+}
+element Rock {
+    color = #686868
+    join_face = SameAlpha
+    conductivity = 20%
+}
+element Gravel : Rock { state = Liquid density = 1800 }
+element Pebble : Gravel { state = Powder }
+element Lava {}",
+        );
+        assert!(diagnostics.is_empty());
+
+        let (granite, _) = elements.get_full_by_name("Granite").unwrap();
+        let (lava, _) = elements.get_full_by_name("Lava").unwrap();
+        let granite = &elements[granite];
+        assert_eq!(granite.color, AtomColor::from_parts(0x8a, 0x7f, 0x80, 0xff));
+        assert_eq!(granite.join_face, JoinFace::SameAlpha);
+        assert_eq!(granite.state, State::Powder);
+        assert_eq!(granite.density, State::Powder.default_density());
+        assert_eq!(granite.conductivity, 0.2);
+        assert_eq!(granite.transitions.melts_into.unwrap().into, lava);
+        // The element's own rules come before the ones it inherits.
+        assert_eq!(granite.rules.len(), 2);
+        assert_eq!(granite.rules[0].pattern, [(IVec3::NEG_Y, Element::AIR_ID)]);
+
+        // A density that's given is kept when the state changes.
+        let (_, pebble) = elements.get_full_by_name("Pebble").unwrap();
+        assert_eq!(pebble.density, 1800.0);
+    }
+
+    #[test]
+    fn inheritance_errors() {
+        for code in [
+            "element Granite : Stone {}",
+            "element Granite : Granite {}",
+            "element Granite : B {}\nelement B : C {}\nelement C : Granite {}",
+        ] {
+            let (elements, diagnostics) = build(code);
+            assert!(diagnostics.has_errored(), "{code} should be an error");
+            assert!(elements.get_full_by_name("Granite").is_some());
+        }
+    }
+
+    const NATURAL: &str = "\
+const STONE_GREY = #686868
+element Stone { color = STONE_GREY melts_into = Lava at 1200°C }
+element Lava { freezes_into = Stone at 1000°C }
+element Water {}
+element Sand {}
+reaction { reactants = { Water Lava } products = { Air Stone } }
+reaction { reactants = { Water Sand } products = { Sand Sand } }";
+
+    fn build_with_natural(code: &str) -> (ParsedSet, Diagnostics) {
+        let mut diagnostics = Diagnostics::init();
+        let mut builder = SetBuilder::named("chemistry");
+        builder.parse_file(code, 0, &mut diagnostics);
+        builder.add_dependency_file("natural", NATURAL, 1);
+        // Never imported, so never parsed.
+        builder.add_dependency_file("broken", "element {", 2);
+        (builder.finish(&mut diagnostics), diagnostics)
+    }
+
+    #[test]
+    fn import_set() {
+        let (set, diagnostics) = build_with_natural(
+            "import natural\nelement Granite : Stone { color = darken(STONE_GREY, 10%) }",
+        );
+        assert!(diagnostics.is_empty());
+
+        let (stone, _) = set.elements.get_full_by_name("Stone").unwrap();
+        let (granite, _) = set.elements.get_full_by_name("Granite").unwrap();
+        let (water, _) = set.elements.get_full_by_name("Water").unwrap();
+        assert!(stone < granite);
+        assert!(set.elements.get_full_by_name("Sand").is_some());
+        assert_eq!(set.reactions.of(water).len(), 2);
+    }
+
+    #[test]
+    fn import_elements() {
+        let (set, diagnostics) = build_with_natural("import natural { Stone Water }");
+        assert!(diagnostics.is_empty());
+
+        // Lava is needed by Stone, but nothing needs Sand.
+        let (water, _) = set.elements.get_full_by_name("Water").unwrap();
+        assert!(set.elements.get_full_by_name("Lava").is_some());
+        assert!(set.elements.get_full_by_name("Sand").is_none());
+        assert_eq!(set.reactions.of(water).len(), 1);
+    }
+
+    #[test]
+    fn import_errors() {
+        for code in [
+            "import geology",
+            "import natural { Granite }",
+            "import natural { #ffffff }",
+            r#"import "natural""#,
+            "import chemistry",
+        ] {
+            let (_, diagnostics) = build_with_natural(code);
+            assert!(diagnostics.has_errored(), "{code} should be an error");
+        }
+
+        let mut diagnostics = Diagnostics::init();
+        let mut builder = SetBuilder::named("chemistry");
+        builder.parse_file("import natural", 0, &mut diagnostics);
+        builder.add_dependency_file("natural", "import geology", 1);
+        builder.add_dependency_file("geology", "import chemistry", 2);
+        builder.finish(&mut diagnostics);
+        assert!(diagnostics.has_errored());
+    }
+
+    #[test]
+    fn neighbor_names() {
+        assert_eq!(rule::neighbor_offset("self"), Some(IVec3::ZERO));
+        assert_eq!(
+            rule::neighbor_offset("below_east"),
+            Some(IVec3::new(1, -1, 0))
+        );
+        assert_eq!(rule::neighbor_offset("below_above"), None);
+        assert_eq!(rule::neighbor_offset("left"), None);
     }
 }
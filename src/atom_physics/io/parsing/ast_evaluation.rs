@@ -1,22 +1,37 @@
 use crate::{
     atom_physics::{
         io::diagnostics::{self, Diagnostic, Positioned},
-        value::ValueUntyped,
+        value::{NumberUnit, ValueUntyped},
     },
     terrain::color::AtomColor,
 };
 
-use super::Ast;
+use std::{cmp::Ordering, mem};
+
+use smartstring::alias::String;
+
+use super::{constants::Constants, Ast, BinaryOp, UnaryOp};
 
 impl<'a> Ast<'a> {
-    pub fn const_eval(&self) -> Result<ValueUntyped<'a>, Positioned<EvalError>> {
+    /// Evaluates the value of the ast.  Identifiers are the values of
+    /// `constants` with the same name, or otherwise enum variants.
+    pub fn const_eval(
+        &self,
+        constants: &Constants<'a>,
+    ) -> Result<ValueUntyped<'a>, Positioned<EvalError>> {
         match self {
             Ast::Block(b) => match b.as_slice() {
                 [] => Ok(ValueUntyped::Unit),
-                [ast] => ast.const_eval(),
+                [ast] => ast.const_eval(constants),
                 _ => Err(b.position.position(EvalError::NotConst)),
             },
-            Ast::Ident(i) => Ok(ValueUntyped::EnumVariant(i)),
+            Ast::Ident(i) => match constants.get(i) {
+                Some(Some(value)) => Ok(value.clone()),
+                Some(None) => Err(i
+                    .position
+                    .position(EvalError::InvalidConstant(i.object.into()))),
+                None => Ok(ValueUntyped::EnumVariant(i)),
+            },
             Ast::HexColor(c) => {
                 for (pos, digit) in c.char_indices() {
                     if !matches!(digit, '0'..='9' | 'a'..='f' | 'A'..='F') {
@@ -68,17 +83,260 @@ impl<'a> Ast<'a> {
                     _ => Err(c.position.position(EvalError::InvalidHexColorLen)),
                 }
             }
+            Ast::Int(n) => n
+                .parse()
+                .map(ValueUntyped::Int)
+                .map_err(|_| n.position.position(EvalError::InvalidNumber)),
+            Ast::Float { value, unit } => {
+                let number = value
+                    .parse()
+                    .map_err(|_| value.position.position(EvalError::InvalidNumber))?;
+                match unit {
+                    Some(unit) => match NumberUnit::from_symbol(unit) {
+                        Some(unit) => Ok(ValueUntyped::Measure(unit.convert(number), unit)),
+                        None => Err(unit.position.position(EvalError::UnknownUnit)),
+                    },
+                    None => Ok(ValueUntyped::Float(number)),
+                }
+            }
+            Ast::Bool(b) => Ok(ValueUntyped::Bool(**b)),
+            Ast::String(s) => {
+                let mut string = String::new();
+                let mut chars = s.char_indices();
+                while let Some((_, ch)) = chars.next() {
+                    if ch != '\\' {
+                        string.push(ch);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some((_, '"')) => string.push('"'),
+                        Some((_, '\\')) => string.push('\\'),
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, 't')) => string.push('\t'),
+                        // Skip the opening quote.
+                        Some((pos, _)) => {
+                            return Err(s
+                                .position
+                                .char_inline(pos + 1)
+                                .position(EvalError::InvalidEscape))
+                        }
+                        None => unreachable!("Strings can't end with a backslash"),
+                    }
+                }
+                Ok(ValueUntyped::String(string))
+            }
             Ast::Element { .. } => Ok(ValueUntyped::Unit),
-            Ast::VariableAssign { .. } => Ok(ValueUntyped::Unit),
+            Ast::VariableAssign { .. } | Ast::Const { .. } | Ast::Import { .. } => {
+                Ok(ValueUntyped::Unit)
+            }
+            Ast::Rule(_) | Ast::Reaction(_) => Ok(ValueUntyped::Unit),
+            Ast::At { .. } => Err(self.position().position(EvalError::NotConst)),
+            Ast::Binary { op, lhs, rhs } => {
+                binary(**op, lhs.operand(constants)?, rhs.operand(constants)?)
+                    .map_err(|e| self.position().position(e))
+            }
+            Ast::Unary { op, operand } => {
+                unary(**op, operand.operand(constants)?).map_err(|e| self.position().position(e))
+            }
+            Ast::Call { function, args } => call(function, args, constants),
+        }
+    }
+
+    /// Evaluates an operand of an operator or an argument of a function.
+    /// Enum variants can't be used there, so identifiers have to be constants.
+    fn operand(
+        &self,
+        constants: &Constants<'a>,
+    ) -> Result<ValueUntyped<'a>, Positioned<EvalError>> {
+        match self {
+            Ast::Ident(i) if !constants.contains(i) => Err(i
+                .position
+                .position(EvalError::UnknownConstant(i.object.into()))),
+            _ => self.const_eval(constants),
         }
     }
 }
 
+fn binary<'a>(
+    op: BinaryOp,
+    lhs: ValueUntyped<'a>,
+    rhs: ValueUntyped<'a>,
+) -> Result<ValueUntyped<'a>, EvalError> {
+    use ValueUntyped::{Bool, Float, Int, Measure};
+
+    let type_error = |lhs: &ValueUntyped, rhs: &ValueUntyped| EvalError::BinaryType {
+        op,
+        lhs: lhs.variant_name(),
+        rhs: rhs.variant_name(),
+    };
+    let divide = |a: f32, b: f32| match b {
+        0.0 => Err(EvalError::DivisionByZero),
+        _ => Ok(a / b),
+    };
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => match (&lhs, &rhs) {
+            (&Int(a), &Int(b)) => match op {
+                BinaryOp::Add => a.checked_add(b).map(Int).ok_or(EvalError::Overflow),
+                BinaryOp::Sub => a.checked_sub(b).map(Int).ok_or(EvalError::Overflow),
+                BinaryOp::Mul => a.checked_mul(b).map(Int).ok_or(EvalError::Overflow),
+                // Division always gives a float, so `1 / 2` is `0.5`.
+                _ => divide(a as f32, b as f32).map(Float),
+            },
+            // Numbers with the same unit can be added and subtracted, and
+            // dividing them gives their ratio.
+            (&Measure(a, a_unit), &Measure(b, b_unit)) if a_unit == b_unit => match op {
+                BinaryOp::Add => Ok(Measure(a + b, a_unit)),
+                BinaryOp::Sub => Ok(Measure(a - b, a_unit)),
+                BinaryOp::Div => divide(a, b).map(Float),
+                _ => Err(type_error(&lhs, &rhs)),
+            },
+            // Numbers with a unit can be scaled by numbers without one.
+            (&Measure(a, unit), b) => match (op, b.as_number(None)) {
+                (BinaryOp::Mul, Some(b)) => Ok(Measure(a * b, unit)),
+                (BinaryOp::Div, Some(b)) => divide(a, b).map(|val| Measure(val, unit)),
+                _ => Err(type_error(&lhs, &rhs)),
+            },
+            (a, &Measure(b, unit)) => match (op, a.as_number(None)) {
+                (BinaryOp::Mul, Some(a)) => Ok(Measure(a * b, unit)),
+                _ => Err(type_error(&lhs, &rhs)),
+            },
+            (a, b) => match (a.as_number(None), b.as_number(None)) {
+                (Some(a), Some(b)) => match op {
+                    BinaryOp::Add => Ok(Float(a + b)),
+                    BinaryOp::Sub => Ok(Float(a - b)),
+                    BinaryOp::Mul => Ok(Float(a * b)),
+                    _ => divide(a, b).map(Float),
+                },
+                _ => Err(type_error(&lhs, &rhs)),
+            },
+        },
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            let ordering = match (&lhs, &rhs) {
+                (Int(a), Int(b)) => Some(a.cmp(b)),
+                (&Measure(a, a_unit), &Measure(b, b_unit)) if a_unit == b_unit => a.partial_cmp(&b),
+                (Measure(..), _) | (_, Measure(..)) => return Err(type_error(&lhs, &rhs)),
+                (a, b) => match (a.as_number(None), b.as_number(None)) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    // Anything else can only be checked for equality with
+                    // values of the same type.
+                    _ if matches!(op, BinaryOp::Eq | BinaryOp::Ne)
+                        && mem::discriminant(a) == mem::discriminant(b) =>
+                    {
+                        (a == b).then_some(Ordering::Equal)
+                    }
+                    _ => return Err(type_error(&lhs, &rhs)),
+                },
+            };
+            let result = match op {
+                BinaryOp::Eq => ordering == Some(Ordering::Equal),
+                BinaryOp::Ne => ordering != Some(Ordering::Equal),
+                BinaryOp::Lt => ordering == Some(Ordering::Less),
+                BinaryOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                BinaryOp::Gt => ordering == Some(Ordering::Greater),
+                _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            };
+            Ok(Bool(result))
+        }
+        BinaryOp::And | BinaryOp::Or => match (lhs, rhs) {
+            (Bool(a), Bool(b)) if op == BinaryOp::And => Ok(Bool(a && b)),
+            (Bool(a), Bool(b)) => Ok(Bool(a || b)),
+            (lhs, rhs) => Err(type_error(&lhs, &rhs)),
+        },
+    }
+}
+
+fn unary(op: UnaryOp, operand: ValueUntyped<'_>) -> Result<ValueUntyped<'_>, EvalError> {
+    match (op, operand) {
+        (UnaryOp::Neg, ValueUntyped::Int(val)) => val
+            .checked_neg()
+            .map(ValueUntyped::Int)
+            .ok_or(EvalError::Overflow),
+        (UnaryOp::Neg, ValueUntyped::Float(val)) => Ok(ValueUntyped::Float(-val)),
+        (UnaryOp::Neg, ValueUntyped::Measure(val, unit)) => Ok(ValueUntyped::Measure(-val, unit)),
+        (UnaryOp::Not, ValueUntyped::Bool(val)) => Ok(ValueUntyped::Bool(!val)),
+        (op, operand) => Err(EvalError::UnaryType {
+            op,
+            operand: operand.variant_name(),
+        }),
+    }
+}
+
+fn call<'a>(
+    function: &Positioned<&'a str>,
+    args: &Positioned<Vec<Ast<'a>>>,
+    constants: &Constants<'a>,
+) -> Result<ValueUntyped<'a>, Positioned<EvalError>> {
+    let arg_count = match **function {
+        "mix" => 3,
+        "darken" | "lighten" => 2,
+        _ => return Err(function.position.position(EvalError::UnknownFunction)),
+    };
+    if args.len() != arg_count {
+        return Err(args.position.position(EvalError::ArgumentCount {
+            expected: arg_count,
+            found: args.len(),
+        }));
+    }
+    let values = args
+        .iter()
+        .map(|arg| arg.operand(constants))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let arg_error = |index: usize, expected: &'static str| {
+        args[index].position().position(EvalError::ArgumentType {
+            expected,
+            found: values[index].variant_name(),
+        })
+    };
+    let color = |index: usize| match values[index] {
+        ValueUntyped::Color(color) => Ok(color),
+        _ => Err(arg_error(index, "Color")),
+    };
+    // Amounts are clamped, so colors can't be mixed past either end.
+    let amount = |index: usize| {
+        values[index]
+            .as_number(Some(NumberUnit::Percent))
+            .map(|amount| amount.clamp(0.0, 1.0))
+            .ok_or_else(|| arg_error(index, NumberUnit::Percent.quantity()))
+    };
+    let color = match **function {
+        "mix" => color(0)?.mix(color(1)?, amount(2)?),
+        "darken" => color(0)?.darken(amount(1)?),
+        _ => color(0)?.lighten(amount(1)?),
+    };
+    Ok(ValueUntyped::Color(color))
+}
+
 #[derive(Debug, Clone)]
 pub enum EvalError {
     NotConst,
     InvalidHexDigit,
     InvalidHexColorLen,
+    InvalidNumber,
+    UnknownUnit,
+    InvalidEscape,
+    BinaryType {
+        op: BinaryOp,
+        lhs: String,
+        rhs: String,
+    },
+    UnaryType {
+        op: UnaryOp,
+        operand: String,
+    },
+    DivisionByZero,
+    Overflow,
+    UnknownFunction,
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    ArgumentType {
+        expected: &'static str,
+        found: String,
+    },
+    UnknownConstant(String),
+    InvalidConstant(String),
 }
 
 impl Diagnostic for EvalError {
@@ -86,7 +344,7 @@ impl Diagnostic for EvalError {
         diagnostics::Level::Error
     }
 
-    fn description(&self) -> String {
+    fn description(&self) -> std::string::String {
         match self {
             EvalError::NotConst => {
                 "Cannot evaluate non-const expression in const context".to_owned()
@@ -98,6 +356,132 @@ impl Diagnostic for EvalError {
                 "Hex colors must be in the format of y, yy, rgb, rgba, rrggbb, or rrggbbaa"
                     .to_owned()
             }
+            EvalError::InvalidNumber => "Invalid number".to_owned(),
+            EvalError::UnknownUnit => "Unknown unit (valid units are °C and %)".to_owned(),
+            EvalError::InvalidEscape => {
+                r#"Invalid escape (valid escapes are \", \\, \n, and \t)"#.to_owned()
+            }
+            EvalError::BinaryType { op, lhs, rhs } => {
+                format!("Cannot apply `{}` to {lhs} and {rhs}", op.symbol())
+            }
+            EvalError::UnaryType { op, operand } => {
+                format!("Cannot apply `{}` to {operand}", op.symbol())
+            }
+            EvalError::DivisionByZero => "Division by zero".to_owned(),
+            EvalError::Overflow => "Integer overflow".to_owned(),
+            EvalError::UnknownFunction => {
+                "Unknown function (valid functions are mix, darken, and lighten)".to_owned()
+            }
+            EvalError::ArgumentCount { expected, found } => {
+                format!("Function takes {expected} arguments, but {found} were given")
+            }
+            EvalError::ArgumentType { expected, found } => {
+                format!("Argument has type {expected}, but found value of type {found}")
+            }
+            EvalError::UnknownConstant(name) => format!("No constant named {name} in this set"),
+            EvalError::InvalidConstant(name) => {
+                format!("Constant {name} has errors, so it can't be used")
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::atom_physics::io::diagnostics::Diagnostics;
+
+    use super::*;
+
+    fn eval(code: &str) -> Result<ValueUntyped<'_>, EvalError> {
+        let mut diagnostics = Diagnostics::init();
+        let asts = Ast::generate(code, 0, &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{code} should parse");
+        match asts.as_slice() {
+            [ast] => ast.const_eval(&Constants::default()).map_err(|e| e.object),
+            _ => panic!("{code} should be a single expression"),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), ValueUntyped::Int(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), ValueUntyped::Int(9));
+        assert_eq!(eval("1 / 2").unwrap(), ValueUntyped::Float(0.5));
+        assert_eq!(eval("-(2 - 0.5)").unwrap(), ValueUntyped::Float(-1.5));
+        assert_eq!(
+            eval("20°C + 5°C * 2").unwrap(),
+            ValueUntyped::Measure(30.0, NumberUnit::Celsius)
+        );
+        assert_eq!(
+            eval("50% / 2").unwrap(),
+            ValueUntyped::Measure(0.25, NumberUnit::Percent)
+        );
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(
+            eval("1 < 2 and not (3 == 4)").unwrap(),
+            ValueUntyped::Bool(true)
+        );
+        assert_eq!(
+            eval("2 <= 1.5 or false").unwrap(),
+            ValueUntyped::Bool(false)
+        );
+        assert_eq!(eval(r#""a" != "b""#).unwrap(), ValueUntyped::Bool(true));
+        assert_eq!(eval("#fff == #fff").unwrap(), ValueUntyped::Bool(true));
+    }
+
+    #[test]
+    fn color_functions() {
+        assert_eq!(
+            eval("mix(#000000, #ffffff, 50%)").unwrap(),
+            ValueUntyped::Color(AtomColor::from_parts(128, 128, 128, 255))
+        );
+        assert_eq!(
+            eval("darken(#686868, 20%)").unwrap(),
+            ValueUntyped::Color(AtomColor::from_parts(0x53, 0x53, 0x53, 255))
+        );
+        assert_eq!(
+            eval("lighten(#00000080, 1)").unwrap(),
+            ValueUntyped::Color(AtomColor::from_parts(255, 255, 255, 0x80))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(eval("1 / 0"), Err(EvalError::DivisionByZero)));
+        assert!(matches!(
+            eval("1.5 / (2 - 2)"),
+            Err(EvalError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval("#fff + 1"),
+            Err(EvalError::BinaryType { .. })
+        ));
+        assert!(matches!(
+            eval("20°C + 50%"),
+            Err(EvalError::BinaryType { .. })
+        ));
+        assert!(matches!(
+            eval("1 and true"),
+            Err(EvalError::BinaryType { .. })
+        ));
+        assert!(matches!(eval("not 1"), Err(EvalError::UnaryType { .. })));
+        assert!(matches!(
+            eval("mix(#fff, 0.5)"),
+            Err(EvalError::ArgumentCount {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            eval("darken(0.5, #fff)"),
+            Err(EvalError::ArgumentType { .. })
+        ));
+        assert!(matches!(
+            eval("blend(#fff)"),
+            Err(EvalError::UnknownFunction)
+        ));
+    }
+}
@@ -1,10 +1,13 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_till, take_while1},
-    character::complete::{alphanumeric1, char, multispace0},
-    combinator::recognize,
+    bytes::complete::{escaped, tag, take_while, take_while1},
+    character::complete::{
+        alpha1, alphanumeric1, anychar, char, digit1, multispace0, multispace1, none_of,
+    },
+    combinator::{cut, opt, recognize},
     error::ErrorKind,
-    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    multi::separated_list0,
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     Parser,
 };
 
@@ -13,7 +16,7 @@ use crate::atom_physics::io::{
     FileId,
 };
 
-use super::Ast;
+use super::{Ast, BinaryOp, UnaryOp};
 
 impl<'a> Ast<'a> {
     pub fn generate(
@@ -44,12 +47,121 @@ fn ast(s: Span<'_>) -> IResult<'_, Ast<'_>> {
     alt((
         block(BlockTy::Bracket).map(Ast::Block),
         element,
+        rule,
+        reaction,
+        import,
+        constant,
         variable_assign,
-        ident.map(Ast::Ident),
+        expression,
+    ))(s)
+}
+
+/// Binary operators grouped by how tightly they bind, loosest first.
+const PRECEDENCE: [&[BinaryOp]; 5] = [
+    &[BinaryOp::Or],
+    &[BinaryOp::And],
+    // Longer symbols first, so `<=` isn't read as `<` then `=`.
+    &[
+        BinaryOp::Eq,
+        BinaryOp::Ne,
+        BinaryOp::Le,
+        BinaryOp::Ge,
+        BinaryOp::Lt,
+        BinaryOp::Gt,
+    ],
+    &[BinaryOp::Add, BinaryOp::Sub],
+    &[BinaryOp::Mul, BinaryOp::Div],
+];
+
+/// Values combined with operators, which bind in the order of [`PRECEDENCE`]
+/// and then unary operators.
+fn expression(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    binary(0, s)
+}
+
+/// Operators at `level` of [`PRECEDENCE`] or tighter.  Operators on the same
+/// level are left associative.
+fn binary(level: usize, s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let Some(ops) = PRECEDENCE.get(level) else {
+        return unary(s);
+    };
+    let (mut s, mut lhs) = binary(level + 1, s)?;
+    loop {
+        let next_op = ops.iter().find_map(|&op| {
+            let (rem, position) = operator(op.symbol())(s).ok()?;
+            Some((rem, position.position(op)))
+        });
+        let Some((rem, op)) = next_op else {
+            return Ok((s, lhs));
+        };
+        let (rem, rhs) = cut(|s| binary(level + 1, s))(rem)?;
+        lhs = Ast::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+        s = rem;
+    }
+}
+
+fn unary(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let prefix = |op: UnaryOp| {
+        pair(operator(op.symbol()), cut(unary)).map(move |(position, operand)| Ast::Unary {
+            op: position.position(op),
+            operand: Box::new(operand),
+        })
+    };
+    // `not` has to be checked before identifiers, and `-` after numbers so
+    // `-1` is a number rather than a negated one.
+    alt((prefix(UnaryOp::Not), primary, prefix(UnaryOp::Neg)))(s)
+}
+
+fn primary(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    alt((
+        parenthesized,
+        call,
+        ident.map(ident_or_bool),
         hex_color,
+        number,
+        string,
     ))(s)
 }
 
+/// An operator's symbol.  Operators made of letters can't be followed by
+/// more letters, so `order` isn't read as `or` then `der`.
+fn operator<'a>(symbol: &'static str) -> impl Fn(Span<'a>) -> IResult<'a, Position> {
+    move |s| {
+        let (rem, op) = tag(symbol)(s)?;
+        let is_word = |ch: char| ch == '_' || ch.is_alphanumeric();
+        if symbol.starts_with(is_word) && rem.starts_with(is_word) {
+            return GenerateErrorKind::Nom(ErrorKind::Tag).at(s).error();
+        }
+        let (rem, _) = multispace0(rem)?;
+        Ok((rem, op.into()))
+    }
+}
+
+fn parenthesized(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    delimited(
+        pair(char('('), multispace0),
+        cut(expression),
+        cut(pair(char(')'), multispace0)),
+    )(s)
+}
+
+/// A function call, such as `mix(#f00, #00f, 50%)`.
+fn call(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let (args_start, function) = ident(s)?;
+    let (rem, args) = delimited(
+        pair(char('('), multispace0),
+        cut(separated_list0(pair(char(','), multispace0), expression)),
+        cut(char(')')),
+    )(args_start)?;
+    let args = Position::from_start_end(args_start, rem).position(args);
+    let (rem, _) = multispace0(rem)?;
+    Ok((rem, Ast::Call { function, args }))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BlockTy {
     Bracket,
@@ -99,7 +211,7 @@ fn ident(s: Span<'_>) -> IResult<'_, Positioned<&'_ str>> {
             take_while1(|ch: char| {
                 !ch.is_whitespace() && !ch.is_ascii_digit() && !ch.is_ascii_punctuation()
             }),
-            take_till(char::is_whitespace),
+            take_while(|ch: char| ch == '_' || (!ch.is_whitespace() && !ch.is_ascii_punctuation())),
         )),
         multispace0,
     )
@@ -113,14 +225,69 @@ fn ident(s: Span<'_>) -> IResult<'_, Positioned<&'_ str>> {
     })
 }
 
+/// `true` and `false` are booleans rather than identifiers.
+fn ident_or_bool(ident: Positioned<&str>) -> Ast<'_> {
+    match *ident {
+        "true" => Ast::Bool(ident.position.position(true)),
+        "false" => Ast::Bool(ident.position.position(false)),
+        _ => Ast::Ident(ident),
+    }
+}
+
 fn hex_color(s: Span<'_>) -> IResult<'_, Ast<'_>> {
     delimited(char('#'), alphanumeric1, multispace0)
         .map(|color: Span| Ast::HexColor(color.into()))
         .parse(s)
 }
 
+/// An integer, or a float if it has a decimal point or a unit.
+fn number(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    terminated(
+        pair(
+            recognize(pair(
+                pair(opt(char('-')), digit1),
+                opt(pair(char('.'), digit1)),
+            )),
+            opt(unit),
+        ),
+        multispace0,
+    )
+    .map(|(value, unit): (Span, Option<Span>)| {
+        if unit.is_none() && !value.contains('.') {
+            Ast::Int(value.into())
+        } else {
+            Ast::Float {
+                value: value.into(),
+                unit: unit.map(Into::into),
+            }
+        }
+    })
+    .parse(s)
+}
+
+/// The unit after a number, such as `%` or `°C`.
+fn unit(s: Span<'_>) -> IResult<'_, Span<'_>> {
+    alt((tag("%"), recognize(pair(char('°'), alpha1))))(s)
+}
+
+/// A quoted string, such as `"Liquid water"`.  Backslashes escape the next
+/// character.
+fn string(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let (rem, contents) = preceded(
+        char('"'),
+        cut(terminated(
+            opt(escaped(none_of("\\\""), '\\', anychar)),
+            char('"'),
+        )),
+    )(s)?;
+    let position = Position::from_start_end(s, rem);
+    let contents = contents.map_or("", |contents| *contents);
+    let (rem, _) = multispace0(rem)?;
+    Ok((rem, Ast::String(position.position(contents))))
+}
+
 fn variable_assign(s: Span<'_>) -> IResult<'_, Ast<'_>> {
-    separated_pair(ident, pair(char('='), multispace0), ast)
+    separated_pair(ident, pair(char('='), multispace0), value)
         .map(|(name, value)| Ast::VariableAssign {
             variable: name,
             value: Box::new(value),
@@ -128,12 +295,55 @@ fn variable_assign(s: Span<'_>) -> IResult<'_, Ast<'_>> {
         .parse(s)
 }
 
+fn constant(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    preceded(
+        tag("const").and(multispace1),
+        separated_pair(ident, pair(char('='), multispace0), value),
+    )
+    .map(|(name, value)| Ast::Const {
+        name,
+        value: Box::new(value),
+    })
+    .parse(s)
+}
+
+/// `import` followed by a value.  Without a value after it, `import` is read
+/// as an identifier instead, so `import = 1` is a variable.
+fn import(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    preceded(
+        operator("import"),
+        pair(primary, opt(block(BlockTy::Bracket))),
+    )
+    .map(|(set, elements)| Ast::Import {
+        set: Box::new(set),
+        elements,
+    })
+    .parse(s)
+}
+
+/// An ast, optionally followed by `at` and another ast.
+fn value(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    pair(ast, opt(preceded(tag("at").and(multispace1), ast)))
+        .map(|(value, at)| match at {
+            Some(at) => Ast::At {
+                value: Box::new(value),
+                at: Box::new(at),
+            },
+            None => value,
+        })
+        .parse(s)
+}
+
 fn element(s: Span<'_>) -> IResult<'_, Ast<'_>> {
     preceded(
         tag("element").and(multispace0),
-        separated_pair(ident, multispace0, block(BlockTy::Bracket)),
+        tuple((
+            ident,
+            opt(preceded(pair(char(':'), multispace0), cut(ident))),
+            block(BlockTy::Bracket),
+        )),
     )
-    .map(|(name, body)| Ast::Element { name, body })
+    .map(|(name, parent, body)| Ast::Element { name, parent, body })
     .parse(s)
     .map_err(|err| {
         err.map(|err| match err.kind {
@@ -146,6 +356,22 @@ fn element(s: Span<'_>) -> IResult<'_, Ast<'_>> {
     })
 }
 
+fn rule(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let (rem, body) = preceded(tag("rule").and(multispace0), block(BlockTy::Bracket))(s)?;
+    Ok((
+        rem,
+        Ast::Rule(Position::from_start_end(s, rem).position(body.object)),
+    ))
+}
+
+fn reaction(s: Span<'_>) -> IResult<'_, Ast<'_>> {
+    let (rem, body) = preceded(tag("reaction").and(multispace0), block(BlockTy::Bracket))(s)?;
+    Ok((
+        rem,
+        Ast::Reaction(Position::from_start_end(s, rem).position(body.object)),
+    ))
+}
+
 fn trim_start(s: Span<'_>) -> Span<'_> {
     multispace0::<_, ()>(s).unwrap().0
 }
@@ -255,6 +481,7 @@ element Bedrock {
 }",
             &[Ast::Element {
                 name: pos("Bedrock"),
+                parent: None,
                 body: pos(vec![Ast::VariableAssign {
                     variable: pos("color"),
                     value: Box::new(Ast::HexColor(pos("686868"))),
@@ -263,6 +490,59 @@ element Bedrock {
         );
     }
 
+    #[test]
+    fn rule() {
+        parsing_test(
+            "\
+rule {
+    match = {below = Air}
+    replace = { self = below below = self }
+}",
+            &[Ast::Rule(pos(vec![
+                Ast::VariableAssign {
+                    variable: pos("match"),
+                    value: Box::new(Ast::Block(pos(vec![Ast::VariableAssign {
+                        variable: pos("below"),
+                        value: Box::new(Ast::Ident(pos("Air"))),
+                    }]))),
+                },
+                Ast::VariableAssign {
+                    variable: pos("replace"),
+                    value: Box::new(Ast::Block(pos(vec![
+                        Ast::VariableAssign {
+                            variable: pos("self"),
+                            value: Box::new(Ast::Ident(pos("below"))),
+                        },
+                        Ast::VariableAssign {
+                            variable: pos("below"),
+                            value: Box::new(Ast::Ident(pos("self"))),
+                        },
+                    ]))),
+                },
+            ]))],
+        );
+    }
+
+    #[test]
+    fn at() {
+        parsing_test(
+            "melts_into = Water at 0 atmosphere = 1",
+            &[
+                Ast::VariableAssign {
+                    variable: pos("melts_into"),
+                    value: Box::new(Ast::At {
+                        value: Box::new(Ast::Ident(pos("Water"))),
+                        at: Box::new(Ast::Int(pos("0"))),
+                    }),
+                },
+                Ast::VariableAssign {
+                    variable: pos("atmosphere"),
+                    value: Box::new(Ast::Int(pos("1"))),
+                },
+            ],
+        );
+    }
+
     #[test]
     fn variable_assign() {
         parsing_test(
@@ -334,6 +614,77 @@ l = #ABCDEfa",
         );
     }
 
+    #[test]
+    fn numbers() {
+        fn va<'a>(name: &'a str, value: Ast<'a>) -> Ast<'a> {
+            Ast::VariableAssign {
+                variable: pos(name),
+                value: Box::new(value),
+            }
+        }
+        fn float<'a>(value: &'a str, unit: Option<&'a str>) -> Ast<'a> {
+            Ast::Float {
+                value: pos(value),
+                unit: unit.map(pos),
+            }
+        }
+
+        parsing_test(
+            "\
+a = 0
+b = 1600
+c = 0.6
+d = -12.25
+e = 20°C
+f = -0.5°C
+g = 50%",
+            &[
+                va("a", Ast::Int(pos("0"))),
+                va("b", Ast::Int(pos("1600"))),
+                va("c", float("0.6", None)),
+                va("d", float("-12.25", None)),
+                va("e", float("20", Some("°C"))),
+                va("f", float("-0.5", Some("°C"))),
+                va("g", float("50", Some("%"))),
+            ],
+        )
+    }
+
+    #[test]
+    fn bools_and_strings() {
+        fn va<'a>(name: &'a str, value: Ast<'a>) -> Ast<'a> {
+            Ast::VariableAssign {
+                variable: pos(name),
+                value: Box::new(value),
+            }
+        }
+
+        parsing_test(
+            r#"
+a = true
+b = false
+c = trueish
+d = "Liquid water"
+e = ""
+f = "say \"hi\" \\ bye""#,
+            &[
+                va("a", Ast::Bool(pos(true))),
+                va("b", Ast::Bool(pos(false))),
+                va("c", Ast::Ident(pos("trueish"))),
+                va("d", Ast::String(pos("Liquid water"))),
+                va("e", Ast::String(pos(""))),
+                va("f", Ast::String(pos(r#"say \"hi\" \\ bye"#))),
+            ],
+        )
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let mut diagnostics = Diagnostics::init();
+        assert!(Ast::generate(r#"name = "Water"#, 0, &mut diagnostics).is_empty());
+        assert!(diagnostics.has_errored());
+    }
+
     #[test]
     fn enum_variants() {
         fn va<'a>(name: &'a str, value: &'a str) -> Ast<'a> {
@@ -351,6 +702,148 @@ b = OtherThing",
         )
     }
 
+    #[test]
+    fn operator_precedence() {
+        fn binary<'a>(op: BinaryOp, lhs: Ast<'a>, rhs: Ast<'a>) -> Ast<'a> {
+            Ast::Binary {
+                op: pos(op),
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }
+        }
+        fn int(value: &str) -> Ast<'_> {
+            Ast::Int(pos(value))
+        }
+        fn ident(value: &str) -> Ast<'_> {
+            Ast::Ident(pos(value))
+        }
+
+        parsing_test(
+            "a = 1 + 2 * (3 - x) >= -4 or not b and order",
+            &[Ast::VariableAssign {
+                variable: pos("a"),
+                value: Box::new(binary(
+                    BinaryOp::Or,
+                    binary(
+                        BinaryOp::Ge,
+                        binary(
+                            BinaryOp::Add,
+                            int("1"),
+                            binary(
+                                BinaryOp::Mul,
+                                int("2"),
+                                binary(BinaryOp::Sub, int("3"), ident("x")),
+                            ),
+                        ),
+                        int("-4"),
+                    ),
+                    binary(
+                        BinaryOp::And,
+                        Ast::Unary {
+                            op: pos(UnaryOp::Not),
+                            operand: Box::new(ident("b")),
+                        },
+                        ident("order"),
+                    ),
+                )),
+            }],
+        );
+    }
+
+    #[test]
+    fn calls() {
+        parsing_test(
+            "color = mix(#f00, darken(#00f, 20%), 0.5)",
+            &[Ast::VariableAssign {
+                variable: pos("color"),
+                value: Box::new(Ast::Call {
+                    function: pos("mix"),
+                    args: pos(vec![
+                        Ast::HexColor(pos("f00")),
+                        Ast::Call {
+                            function: pos("darken"),
+                            args: pos(vec![
+                                Ast::HexColor(pos("00f")),
+                                Ast::Float {
+                                    value: pos("20"),
+                                    unit: Some(pos("%")),
+                                },
+                            ]),
+                        },
+                        Ast::Float {
+                            value: pos("0.5"),
+                            unit: None,
+                        },
+                    ]),
+                }),
+            }],
+        );
+    }
+
+    #[test]
+    fn constant() {
+        parsing_test(
+            "const STONE_GREY = #686868\nconstant = 1",
+            &[
+                Ast::Const {
+                    name: pos("STONE_GREY"),
+                    value: Box::new(Ast::HexColor(pos("686868"))),
+                },
+                Ast::VariableAssign {
+                    variable: pos("constant"),
+                    value: Box::new(Ast::Int(pos("1"))),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn element_with_parent() {
+        parsing_test(
+            "element Granite : Stone { color = #8a7f80 }",
+            &[Ast::Element {
+                name: pos("Granite"),
+                parent: Some(pos("Stone")),
+                body: pos(vec![Ast::VariableAssign {
+                    variable: pos("color"),
+                    value: Box::new(Ast::HexColor(pos("8a7f80"))),
+                }]),
+            }],
+        );
+    }
+
+    #[test]
+    fn import() {
+        parsing_test(
+            "import natural\nimport geology { Granite Basalt }\nimportance = 1\nimport = 2\nimport \"x\"",
+            &[
+                Ast::Import {
+                    set: Box::new(Ast::Ident(pos("natural"))),
+                    elements: None,
+                },
+                Ast::Import {
+                    set: Box::new(Ast::Ident(pos("geology"))),
+                    elements: Some(pos(vec![
+                        Ast::Ident(pos("Granite")),
+                        Ast::Ident(pos("Basalt")),
+                    ])),
+                },
+                Ast::VariableAssign {
+                    variable: pos("importance"),
+                    value: Box::new(Ast::Int(pos("1"))),
+                },
+                Ast::VariableAssign {
+                    variable: pos("import"),
+                    value: Box::new(Ast::Int(pos("2"))),
+                },
+                Ast::Import {
+                    set: Box::new(Ast::String(pos("x"))),
+                    elements: None,
+                },
+            ],
+        );
+    }
+
     #[test]
     fn value_in_block() {
         parsing_test(
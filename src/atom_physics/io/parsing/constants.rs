@@ -0,0 +1,193 @@
+//! Top-level `const` definitions.
+//!
+//! Constants can be used anywhere in their set, including in other constants
+//! and in files read before the one defining them, so every definition is
+//! collected before any are evaluated.
+
+use std::collections::{HashMap, HashSet};
+
+use indexmap::IndexMap;
+use smartstring::alias::String;
+
+use crate::atom_physics::{
+    io::diagnostics::{self, Diagnostic, Diagnostics, Positioned},
+    value::ValueUntyped,
+};
+
+use super::Ast;
+
+/// The values of a set's constants, by name.
+#[derive(Debug, Default)]
+pub struct Constants<'a> {
+    /// `None` for constants that couldn't be evaluated, which has already
+    /// been reported.
+    values: HashMap<&'a str, Option<ValueUntyped<'a>>>,
+}
+
+impl<'a> Constants<'a> {
+    /// Evaluates the `const` definitions among `asts`, each after the
+    /// constants it uses.
+    pub fn resolve(asts: &[Ast<'a>], diagnostics: &mut Diagnostics) -> Self {
+        let mut definitions: IndexMap<&str, (Positioned<&str>, &Ast)> = IndexMap::new();
+        for ast in asts {
+            let Ast::Const { name, value } = ast else {
+                continue;
+            };
+            match definitions.get(**name) {
+                Some(&(first, _)) => {
+                    diagnostics.add(name.position, ConstError::DoubleDefine(name.object.into()));
+                    diagnostics.add(
+                        first.position,
+                        ConstError::FirstDefinition(name.object.into()),
+                    );
+                }
+                None => {
+                    definitions.insert(**name, (*name, &**value));
+                }
+            }
+        }
+
+        let mut resolver = Resolver {
+            definitions,
+            constants: Self::default(),
+            stack: Vec::new(),
+            in_cycle: HashSet::new(),
+        };
+        for index in 0..resolver.definitions.len() {
+            let (&name, _) = resolver.definitions.get_index(index).unwrap();
+            resolver.resolve(name, diagnostics);
+        }
+        resolver.constants
+    }
+
+    /// The value of the constant called `name`, or `Some(None)` if it's a
+    /// constant that couldn't be evaluated.
+    pub fn get(&self, name: &str) -> Option<Option<&ValueUntyped<'a>>> {
+        self.values.get(name).map(Option::as_ref)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+}
+
+struct Resolver<'a, 'd> {
+    definitions: IndexMap<&'a str, (Positioned<&'a str>, &'d Ast<'a>)>,
+    constants: Constants<'a>,
+    /// The constants being resolved, each used by the one before it.
+    stack: Vec<&'a str>,
+    /// Constants that use themselves, which have already been reported.
+    in_cycle: HashSet<&'a str>,
+}
+
+impl<'a, 'd> Resolver<'a, 'd> {
+    fn resolve(&mut self, name: &'a str, diagnostics: &mut Diagnostics) {
+        if self.constants.contains(name) {
+            return;
+        }
+        if let Some(start) = self.stack.iter().position(|&other| other == name) {
+            let cycle = &self.stack[start..];
+            let mut path = String::new();
+            for other in cycle {
+                path.push_str(other);
+                path.push_str(" -> ");
+            }
+            path.push_str(name);
+            let (definition, _) = self.definitions[name];
+            diagnostics.add(
+                definition.position,
+                ConstError::Cycle {
+                    name: name.into(),
+                    path,
+                },
+            );
+            self.in_cycle.extend(cycle);
+            return;
+        }
+
+        let (_, value) = self.definitions[name];
+        self.stack.push(name);
+        let mut references = Vec::new();
+        identifiers(value, &mut references);
+        for reference in references {
+            if self.definitions.contains_key(reference) {
+                self.resolve(reference, diagnostics);
+            }
+        }
+        self.stack.pop();
+
+        let value = if self.in_cycle.contains(name) {
+            None
+        } else {
+            match value.const_eval(&self.constants) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    diagnostics.add_positioned(e);
+                    None
+                }
+            }
+        };
+        self.constants.values.insert(name, value);
+    }
+}
+
+/// Every identifier used in `ast`, in order.
+pub(super) fn identifiers<'a>(ast: &Ast<'a>, out: &mut Vec<&'a str>) {
+    match ast {
+        Ast::Ident(i) => out.push(i),
+        Ast::Block(asts)
+        | Ast::Rule(asts)
+        | Ast::Reaction(asts)
+        | Ast::Element { body: asts, .. }
+        | Ast::Call { args: asts, .. } => {
+            for ast in asts.iter() {
+                identifiers(ast, out);
+            }
+        }
+        Ast::VariableAssign { value, .. } | Ast::Const { value, .. } => identifiers(value, out),
+        Ast::At {
+            value: lhs,
+            at: rhs,
+        }
+        | Ast::Binary { lhs, rhs, .. } => {
+            identifiers(lhs, out);
+            identifiers(rhs, out);
+        }
+        Ast::Unary { operand, .. } => identifiers(operand, out),
+        Ast::HexColor(_)
+        | Ast::Int(_)
+        | Ast::Float { .. }
+        | Ast::Bool(_)
+        | Ast::String(_)
+        | Ast::Import { .. } => {}
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ConstError {
+    DoubleDefine(String),
+    FirstDefinition(String),
+    Cycle { name: String, path: String },
+}
+
+impl Diagnostic for ConstError {
+    fn level(&self) -> diagnostics::Level {
+        match self {
+            ConstError::DoubleDefine(_) => diagnostics::Level::Warn,
+            ConstError::FirstDefinition(_) => diagnostics::Level::Note,
+            ConstError::Cycle { .. } => diagnostics::Level::Error,
+        }
+    }
+
+    fn description(&self) -> std::string::String {
+        match self {
+            ConstError::DoubleDefine(name) => {
+                format!("Constant {name} defined twice; using first definition")
+            }
+            ConstError::FirstDefinition(name) => format!("Constant {name} first defined here"),
+            ConstError::Cycle { name, path } => {
+                format!("Constant {name} depends on itself ({path})")
+            }
+        }
+    }
+}
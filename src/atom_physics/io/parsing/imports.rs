@@ -0,0 +1,265 @@
+//! `import` statements, which bring what another set defines into the set
+//! being built.
+//!
+//! `import natural` brings in everything `natural` defines, including what it
+//! imports itself.  `import natural { Water Sand }` brings in only the listed
+//! elements and the ones they need, such as their parents and the elements
+//! they turn into, along with `natural`'s constants and the reactions between
+//! the elements brought in.  Imported items come before the set's own, so
+//! elements keep the same order however a set is imported.
+
+use std::collections::{HashMap, HashSet};
+
+use indexmap::IndexSet;
+use smartstring::alias::String;
+
+use crate::atom_physics::io::{
+    diagnostics::{self, Diagnostic, Diagnostics, Positioned},
+    FileId,
+};
+
+use super::{constants::identifiers, Ast};
+
+/// The names of the sets imported by a file, so they can be read before the
+/// set is built.
+pub fn imported_sets(code: &str, file: FileId) -> Vec<String> {
+    // Anything wrong with the file is reported when the set is built.
+    Ast::generate(code, file, &mut Diagnostics::init())
+        .into_iter()
+        .filter_map(|ast| match ast {
+            Ast::Import { set, .. } => match *set {
+                Ast::Ident(name) => Some(name.object.into()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// An item of a set, as the index of the set and the index of the item in
+/// it.
+type Item = (usize, usize);
+
+/// Replaces the imports among `asts` with what they import, parsing the
+/// files of the imported sets from `dependencies`.  `name` is the name of the
+/// set being built, if it has one.
+pub fn resolve<'a>(
+    name: Option<&str>,
+    asts: Vec<Ast<'a>>,
+    dependencies: &HashMap<String, Vec<(FileId, &'a str)>>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<Ast<'a>> {
+    let mut resolver = Resolver {
+        dependencies,
+        sets: vec![(name.unwrap_or_default().into(), asts)],
+        by_name: name.map(|name| (name.into(), 0)).into_iter().collect(),
+        items: vec![None],
+        stack: Vec::new(),
+    };
+    let items = resolver.resolve(0, diagnostics);
+    items
+        .into_iter()
+        .map(|item| resolver.ast(item).clone())
+        .collect()
+}
+
+struct Resolver<'a, 'd> {
+    dependencies: &'d HashMap<String, Vec<(FileId, &'a str)>>,
+    /// The name and items of each set that's been parsed, starting with the
+    /// one being built.
+    sets: Vec<(String, Vec<Ast<'a>>)>,
+    by_name: HashMap<String, usize>,
+    /// Everything each set brings in, once its imports are resolved.
+    items: Vec<Option<IndexSet<Item>>>,
+    /// The sets being resolved, each imported by the one before it.
+    stack: Vec<usize>,
+}
+
+impl<'a> Resolver<'a, '_> {
+    fn ast(&self, (set, index): Item) -> &Ast<'a> {
+        &self.sets[set].1[index]
+    }
+
+    /// Finds the set called `name`, parsing it if it hasn't been already.
+    fn set(&mut self, name: &str, diagnostics: &mut Diagnostics) -> Option<usize> {
+        if let Some(&set) = self.by_name.get(name) {
+            return Some(set);
+        }
+        let files = self.dependencies.get(name)?;
+        let mut asts = Vec::new();
+        for &(file, code) in files {
+            asts.extend(Ast::generate(code, file, diagnostics));
+        }
+        let set = self.sets.len();
+        self.sets.push((name.into(), asts));
+        self.by_name.insert(name.into(), set);
+        self.items.push(None);
+        Some(set)
+    }
+
+    fn resolve(&mut self, set: usize, diagnostics: &mut Diagnostics) -> IndexSet<Item> {
+        if let Some(items) = &self.items[set] {
+            return items.clone();
+        }
+
+        self.stack.push(set);
+        let mut items = IndexSet::new();
+        for index in 0..self.sets[set].1.len() {
+            let Ast::Import {
+                set: name,
+                elements,
+            } = self.ast((set, index))
+            else {
+                continue;
+            };
+            let Ast::Ident(name) = **name else {
+                diagnostics.add(name.position(), ImportError::ExpectedSetName);
+                continue;
+            };
+            let elements = elements.clone();
+            let Some(dependency) = self.set(&name, diagnostics) else {
+                diagnostics.add(name.position, ImportError::UnknownSet(name.object.into()));
+                continue;
+            };
+            if let Some(start) = self.stack.iter().position(|&other| other == dependency) {
+                let mut path = String::new();
+                for &other in &self.stack[start..] {
+                    path.push_str(&self.sets[other].0);
+                    path.push_str(" -> ");
+                }
+                path.push_str(&name);
+                diagnostics.add(
+                    name.position,
+                    ImportError::Cycle {
+                        name: name.object.into(),
+                        path,
+                    },
+                );
+                continue;
+            }
+
+            let imported = self.resolve(dependency, diagnostics);
+            match elements {
+                Some(elements) => {
+                    items.extend(self.select(&imported, name, &elements, diagnostics))
+                }
+                None => items.extend(imported),
+            }
+        }
+        self.stack.pop();
+
+        for (index, ast) in self.sets[set].1.iter().enumerate() {
+            if !matches!(ast, Ast::Import { .. }) {
+                items.insert((set, index));
+            }
+        }
+        self.items[set] = Some(items.clone());
+        items
+    }
+
+    /// The items of `imported` needed by the elements listed in `elements`.
+    fn select(
+        &self,
+        imported: &IndexSet<Item>,
+        set: Positioned<&str>,
+        elements: &[Ast<'_>],
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<Item> {
+        let mut definitions = HashMap::new();
+        for &item in imported {
+            if let Ast::Element { name, .. } | Ast::Const { name, .. } = self.ast(item) {
+                definitions.entry(**name).or_insert(item);
+            }
+        }
+        let is_element = |item| matches!(self.ast(item), Ast::Element { .. });
+
+        let mut names = Vec::new();
+        for ast in elements {
+            match ast {
+                Ast::Ident(name) if definitions.get(**name).is_some_and(|&i| is_element(i)) => {
+                    names.push(**name)
+                }
+                Ast::Ident(name) => diagnostics.add(
+                    name.position,
+                    ImportError::UnknownElement {
+                        set: set.object.into(),
+                        element: name.object.into(),
+                    },
+                ),
+                _ => diagnostics.add(ast.position(), ImportError::ExpectedElementName),
+            }
+        }
+
+        // Follows the names used by each element, and by the constants they
+        // use, to every element they need.
+        let mut needed = HashSet::new();
+        while let Some(name) = names.pop() {
+            let Some(&item) = definitions.get(name) else {
+                continue;
+            };
+            if !needed.insert(item) {
+                continue;
+            }
+            let ast = self.ast(item);
+            if let Ast::Element {
+                parent: Some(parent),
+                ..
+            } = ast
+            {
+                names.push(**parent);
+            }
+            identifiers(ast, &mut names);
+        }
+
+        imported
+            .iter()
+            .copied()
+            .filter(|&item| match self.ast(item) {
+                Ast::Element { .. } => needed.contains(&item),
+                reaction @ Ast::Reaction(_) => {
+                    let mut names = Vec::new();
+                    identifiers(reaction, &mut names);
+                    names.into_iter().all(|name| {
+                        definitions
+                            .get(name)
+                            .is_none_or(|&i| !is_element(i) || needed.contains(&i))
+                    })
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ImportError {
+    UnknownSet(String),
+    Cycle { name: String, path: String },
+    UnknownElement { set: String, element: String },
+    ExpectedSetName,
+    ExpectedElementName,
+}
+
+impl Diagnostic for ImportError {
+    fn level(&self) -> diagnostics::Level {
+        diagnostics::Level::Error
+    }
+
+    fn description(&self) -> std::string::String {
+        match self {
+            ImportError::UnknownSet(name) => format!("No set named {name}"),
+            ImportError::Cycle { name, path } => {
+                format!("Set {name} imports itself ({path})")
+            }
+            ImportError::UnknownElement { set, element } => {
+                format!("No element named {element} in set {set}")
+            }
+            ImportError::ExpectedSetName => {
+                "Expected the name of a set to import, such as `natural`".to_owned()
+            }
+            ImportError::ExpectedElementName => {
+                "Expected the names of elements to import, such as `{ Water Sand }`".to_owned()
+            }
+        }
+    }
+}
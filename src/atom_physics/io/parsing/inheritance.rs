@@ -0,0 +1,135 @@
+//! Elements that extend another element, written
+//! `element Granite : Stone { ... }`.
+//!
+//! A parent can be defined after the elements extending it, or in another
+//! file of the set, so every definition is collected before any are parsed.
+
+use std::collections::HashMap;
+
+use smartstring::alias::String;
+
+use crate::atom_physics::io::diagnostics::{self, Diagnostic, Diagnostics, Position, Positioned};
+
+use super::{constants::Constants, parse_element, Ast, ParsedElement};
+
+/// Parses the elements defined among `asts`, each after its parent.  Returns
+/// the name and position of each definition, in the order they're defined.
+pub fn parse_elements<'a>(
+    asts: &[Ast<'a>],
+    constants: &Constants<'_>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<(Positioned<&'a str>, Position, ParsedElement)> {
+    let mut definitions = Vec::new();
+    let mut by_name = HashMap::new();
+    for ast in asts {
+        if let Ast::Element { name, parent, body } = ast {
+            by_name.entry(**name).or_insert(definitions.len());
+            definitions.push(Definition {
+                name: *name,
+                parent: *parent,
+                body,
+                position: ast.position(),
+            });
+        }
+    }
+
+    let mut resolver = Resolver {
+        parsed: vec![None; definitions.len()],
+        definitions,
+        by_name,
+        constants,
+        stack: Vec::new(),
+    };
+    for index in 0..resolver.definitions.len() {
+        resolver.resolve(index, diagnostics);
+    }
+    resolver
+        .definitions
+        .into_iter()
+        .zip(resolver.parsed)
+        .map(|(definition, parsed)| (definition.name, definition.position, parsed.unwrap()))
+        .collect()
+}
+
+struct Definition<'a, 'd> {
+    name: Positioned<&'a str>,
+    parent: Option<Positioned<&'a str>>,
+    body: &'d [Ast<'a>],
+    position: Position,
+}
+
+struct Resolver<'a, 'd, 'c> {
+    definitions: Vec<Definition<'a, 'd>>,
+    /// The first definition of each name, which is the one other elements
+    /// extend.
+    by_name: HashMap<&'a str, usize>,
+    parsed: Vec<Option<ParsedElement>>,
+    constants: &'c Constants<'c>,
+    /// The elements being parsed, each extending the one after it.
+    stack: Vec<usize>,
+}
+
+impl Resolver<'_, '_, '_> {
+    fn resolve(&mut self, index: usize, diagnostics: &mut Diagnostics) {
+        if self.parsed[index].is_some() {
+            return;
+        }
+        let definition = &self.definitions[index];
+        let (body, parent) = (definition.body, definition.parent);
+
+        self.stack.push(index);
+        let parent = parent.and_then(|parent| match self.by_name.get(*parent) {
+            None => {
+                diagnostics.add(
+                    parent.position,
+                    InheritError::UnknownParent(parent.object.into()),
+                );
+                None
+            }
+            Some(&parent_index) => {
+                if let Some(start) = self.stack.iter().position(|&i| i == parent_index) {
+                    let mut path = String::new();
+                    for &i in &self.stack[start..] {
+                        path.push_str(&self.definitions[i].name);
+                        path.push_str(" -> ");
+                    }
+                    path.push_str(&parent);
+                    let name = self.definitions[index].name.object.into();
+                    diagnostics.add(parent.position, InheritError::Cycle { name, path });
+                    None
+                } else {
+                    self.resolve(parent_index, diagnostics);
+                    Some(parent_index)
+                }
+            }
+        });
+        self.stack.pop();
+
+        let parent = parent.and_then(|parent| self.parsed[parent].as_ref());
+        let parsed = parse_element(body, parent, self.constants, diagnostics);
+        self.parsed[index] = Some(parsed);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum InheritError {
+    UnknownParent(String),
+    Cycle { name: String, path: String },
+}
+
+impl Diagnostic for InheritError {
+    fn level(&self) -> diagnostics::Level {
+        diagnostics::Level::Error
+    }
+
+    fn description(&self) -> std::string::String {
+        match self {
+            InheritError::UnknownParent(name) => {
+                format!("No element named {name} in this set to extend")
+            }
+            InheritError::Cycle { name, path } => {
+                format!("Element {name} extends itself ({path})")
+            }
+        }
+    }
+}
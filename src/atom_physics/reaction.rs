@@ -0,0 +1,50 @@
+use super::element::ElementId;
+
+/// Two touching atoms turning into two new atoms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reaction<E = ElementId> {
+    /// The atom the reaction is checked for, and the neighbour it must touch.
+    pub reactants: [E; 2],
+    /// What each reactant is replaced with.
+    pub products: [E; 2],
+    /// Chance each tick that touching reactants react, between 0 and 1.
+    pub probability: f32,
+}
+
+impl<E> Reaction<E> {
+    /// Converts every element this references, returning `None` if any of
+    /// them could not be converted.  `f` is called for every element, even
+    /// after one fails.
+    pub fn resolve<F>(self, mut f: impl FnMut(E) -> Option<F>) -> Option<Reaction<F>> {
+        let [a, b] = self.reactants.map(&mut f);
+        let [c, d] = self.products.map(&mut f);
+        Some(Reaction {
+            reactants: [a?, b?],
+            products: [c?, d?],
+            probability: self.probability,
+        })
+    }
+}
+
+/// Every reaction in the loaded set, grouped by their first reactant.
+#[derive(Debug, Clone, Default)]
+pub struct Reactions {
+    by_element: Vec<Vec<Reaction>>,
+}
+
+impl Reactions {
+    pub fn insert(&mut self, reaction: Reaction) {
+        let index = reaction.reactants[0] as usize;
+        if self.by_element.len() <= index {
+            self.by_element.resize_with(index + 1, Vec::new);
+        }
+        self.by_element[index].push(reaction);
+    }
+
+    /// Reactions whose first reactant is `element`.
+    pub fn of(&self, element: ElementId) -> &[Reaction] {
+        self.by_element
+            .get(element as usize)
+            .map_or(&[], Vec::as_slice)
+    }
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::IVec3;
+
+use super::element::ElementId;
+
+/// A neighbour-pattern → replacement transition, evaluated for every atom of
+/// the element it belongs to each simulation tick.
+///
+/// All positions are offsets from the atom the rule is being evaluated for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule<E = ElementId> {
+    /// Elements that must be at each position for the rule to apply.
+    pub pattern: Vec<(IVec3, E)>,
+    /// What each position is replaced with when the rule applies.
+    pub replace: Vec<(IVec3, Replacement<E>)>,
+    /// Chance each tick that the rule is used when its pattern matches,
+    /// between 0 and 1.
+    pub probability: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement<E = ElementId> {
+    /// A new atom of an element.
+    Element(E),
+    /// The atom that was at a position before the rule applied.
+    Atom(IVec3),
+}
+
+impl<E> Rule<E> {
+    /// Converts every element this references, returning `None` if any of
+    /// them could not be converted.  `f` is called for every element, even
+    /// after one fails.
+    pub fn resolve<F>(self, mut f: impl FnMut(E) -> Option<F>) -> Option<Rule<F>> {
+        let pattern: Vec<_> = self
+            .pattern
+            .into_iter()
+            .map(|(offset, element)| f(element).map(|element| (offset, element)))
+            .collect();
+        let replace: Vec<_> = self
+            .replace
+            .into_iter()
+            .map(|(offset, replacement)| {
+                let replacement = match replacement {
+                    Replacement::Element(element) => Replacement::Element(f(element)?),
+                    Replacement::Atom(from) => Replacement::Atom(from),
+                };
+                Some((offset, replacement))
+            })
+            .collect();
+
+        Some(Rule {
+            pattern: pattern.into_iter().collect::<Option<_>>()?,
+            replace: replace.into_iter().collect::<Option<_>>()?,
+            probability: self.probability,
+        })
+    }
+}
+
+/// Converts a neighbour name used in rules into an offset.
+///
+/// Names are `self`, or up to one of each of `above`/`below`,
+/// `north`/`south`, and `east`/`west` joined by underscores, such as
+/// `below_east`.
+pub fn neighbor_offset(name: &str) -> Option<IVec3> {
+    if name == "self" {
+        return Some(IVec3::ZERO);
+    }
+
+    let mut offset = IVec3::ZERO;
+    for part in name.split('_') {
+        let (axis, dir) = match part {
+            "above" => (IVec3::Y, 1),
+            "below" => (IVec3::Y, -1),
+            "east" => (IVec3::X, 1),
+            "west" => (IVec3::X, -1),
+            "south" => (IVec3::Z, 1),
+            "north" => (IVec3::Z, -1),
+            _ => return None,
+        };
+        if (offset * axis) != IVec3::ZERO {
+            return None;
+        }
+        offset += axis * dir;
+    }
+    Some(offset)
+}
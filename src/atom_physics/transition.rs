@@ -0,0 +1,67 @@
+use super::element::ElementId;
+
+/// Turns an atom into another element once it passes a temperature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseTransition<E = ElementId> {
+    pub into: E,
+    /// In °C.
+    pub temperature: f32,
+}
+
+/// The phase transitions of an element.  Atoms keep their temperature when
+/// they change element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transitions<E = ElementId> {
+    /// Used when an atom gets hotter than the temperature.
+    pub melts_into: Option<PhaseTransition<E>>,
+    /// Used when an atom gets colder than the temperature.
+    pub freezes_into: Option<PhaseTransition<E>>,
+    /// Used when an atom gets hotter than the temperature.  Checked before
+    /// `melts_into`.
+    pub boils_into: Option<PhaseTransition<E>>,
+}
+
+impl<E> Default for Transitions<E> {
+    fn default() -> Self {
+        Self {
+            melts_into: None,
+            freezes_into: None,
+            boils_into: None,
+        }
+    }
+}
+
+impl<E> Transitions<E> {
+    /// Converts every element this references, dropping transitions whose
+    /// element could not be converted.
+    pub fn resolve<F>(self, mut f: impl FnMut(E) -> Option<F>) -> Transitions<F> {
+        let mut resolve = |transition: Option<PhaseTransition<E>>| {
+            transition.and_then(|PhaseTransition { into, temperature }| {
+                Some(PhaseTransition {
+                    into: f(into)?,
+                    temperature,
+                })
+            })
+        };
+        Transitions {
+            melts_into: resolve(self.melts_into),
+            freezes_into: resolve(self.freezes_into),
+            boils_into: resolve(self.boils_into),
+        }
+    }
+}
+
+impl Transitions {
+    /// The element an atom at `temperature` should turn into, if any.
+    pub fn at(&self, temperature: f32) -> Option<ElementId> {
+        [
+            self.boils_into.filter(|t| temperature > t.temperature),
+            self.melts_into.filter(|t| temperature > t.temperature),
+            self.freezes_into.filter(|t| temperature < t.temperature),
+        ]
+        .into_iter()
+        .flatten()
+        .next()
+        .map(|t| t.into)
+    }
+}
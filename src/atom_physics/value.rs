@@ -2,9 +2,16 @@ use crate::terrain::color::AtomColor;
 
 use smartstring::alias::String;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValueUntyped<'a> {
     Color(AtomColor),
+    Int(i64),
+    Float(f32),
+    /// A number written with a unit, converted to the unit the simulation
+    /// uses.
+    Measure(f32, NumberUnit),
+    Bool(bool),
+    String(String),
     EnumVariant(&'a str),
     Unit,
 }
@@ -13,8 +20,60 @@ impl<'a> ValueUntyped<'a> {
     pub fn variant_name(&self) -> String {
         match self {
             ValueUntyped::Color(_) => "Color".into(),
+            ValueUntyped::Int(_) => "Int".into(),
+            ValueUntyped::Float(_) => "Float".into(),
+            ValueUntyped::Measure(_, unit) => unit.quantity().into(),
+            ValueUntyped::Bool(_) => "Bool".into(),
+            ValueUntyped::String(_) => "String".into(),
             ValueUntyped::EnumVariant(v) => format!("{{ {v} }}").into(),
             ValueUntyped::Unit => "()".into(),
         }
     }
+
+    /// The value as a number if it's an integer, a float, or a number written
+    /// in `unit`.
+    pub fn as_number(&self, unit: Option<NumberUnit>) -> Option<f32> {
+        match *self {
+            ValueUntyped::Int(val) => Some(val as f32),
+            ValueUntyped::Float(val) => Some(val),
+            ValueUntyped::Measure(val, val_unit) if Some(val_unit) == unit => Some(val),
+            _ => None,
+        }
+    }
+}
+
+/// Units that numbers can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberUnit {
+    /// `°C`, which temperatures are measured in.
+    Celsius,
+    /// `%`, converted to a fraction, so `50%` is `0.5`.
+    Percent,
+}
+
+impl NumberUnit {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "°C" => Some(NumberUnit::Celsius),
+            "%" => Some(NumberUnit::Percent),
+            _ => None,
+        }
+    }
+
+    /// Converts a number written in this unit to the unit the simulation
+    /// uses.
+    pub fn convert(self, val: f32) -> f32 {
+        match self {
+            NumberUnit::Celsius => val,
+            NumberUnit::Percent => val / 100.0,
+        }
+    }
+
+    /// What is measured in this unit.
+    pub fn quantity(self) -> &'static str {
+        match self {
+            NumberUnit::Celsius => "Temperature",
+            NumberUnit::Percent => "Percentage",
+        }
+    }
 }
@@ -8,17 +8,35 @@ mod terrain;
 mod ui;
 
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins,
-            EguiPlugin,
-            atom_physics::AtomPhysicsPlugin,
-            player::PlayerPlugin,
-            terrain::TerrainPlugin,
-            ui::UiPlugin,
-        ))
-        .add_systems(Startup, setup_window_system)
-        .run();
+    let mut app = App::new();
+    if let Some(size) = world_size_arg() {
+        app.insert_resource(terrain::InitialWorldSize(size));
+    }
+    app.add_plugins((
+        DefaultPlugins,
+        EguiPlugin,
+        atom_physics::AtomPhysicsPlugin,
+        player::PlayerPlugin,
+        terrain::TerrainPlugin,
+        ui::UiPlugin,
+    ))
+    .add_systems(Startup, setup_window_system)
+    .run();
+}
+
+/// Reads the size of the world from a `--world-size 128x48x256` flag.
+fn world_size_arg() -> Option<UVec3> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--world-size");
+    args.next()?;
+    let arg = args.next().unwrap_or_default();
+    let size = terrain::parse_size(&arg);
+    if size.is_none() {
+        eprintln!(
+            "Invalid world size {arg:?}, expected sides that are multiples of {} like 128x48x256",
+            terrain::rendering::CHUNK_SIZE
+        );
+    }
+    size
 }
 
 /// Setup system that sets window title and hides and grabs the cursor.
@@ -6,7 +6,11 @@ use bevy::prelude::*;
 
 use crate::{
     atom_physics::element::ElementId,
-    terrain::storage::{Atoms, RaycastHit},
+    terrain::{
+        rendering::CHUNK_SIZE,
+        storage::{Atoms, RaycastHit},
+        thread::TerrainThread,
+    },
     ui::CursorGrabbed,
 };
 
@@ -54,6 +58,7 @@ impl Plugin for PlayerPlugin {
             (
                 apply_friction_system,
                 player_look_pos_system.in_set(PlayerUpdateSet::TargetPos),
+                send_focus_system,
             )
                 .after(PlayerUpdateSet::Move),
         );
@@ -189,3 +194,23 @@ fn player_look_pos_system(
 
     look_pos.0 = world.raycast(ray, config.reach_dist, |atom| atom.is_visible());
 }
+
+/// Tells the terrain thread which chunk the player is in whenever it changes,
+/// so the chunks around them are kept loaded.
+fn send_focus_system(
+    player_query: Query<&Transform, With<Player>>,
+    thread: Res<TerrainThread>,
+    mut last_chunk: Local<Option<UVec3>>,
+) {
+    let pos = player_query
+        .single()
+        .translation
+        .round()
+        .max(Vec3::ZERO)
+        .as_uvec3();
+    let chunk = pos / CHUNK_SIZE as u32;
+    if *last_chunk != Some(chunk) {
+        *last_chunk = Some(chunk);
+        thread.set_focus(pos);
+    }
+}
@@ -22,6 +22,9 @@ pub struct Bindings {
 
     pub break_atom: Button,
     pub place_atom: Button,
+    /// Pressed once to mark one corner of a box and again at the opposite
+    /// corner to fill the box.
+    pub fill_box: Button,
 }
 
 impl Default for Bindings {
@@ -50,6 +53,7 @@ impl Default for Bindings {
 
             break_atom: Button::Mouse(MouseButton::Left),
             place_atom: Button::Mouse(MouseButton::Right),
+            fill_box: Button::Key(KeyCode::F),
         }
     }
 }
@@ -1,8 +1,11 @@
 use bevy::prelude::*;
 
 use crate::{
-    atom_physics::{element::Element, id::IdMap},
-    terrain::storage::Atoms,
+    atom_physics::element::Element,
+    terrain::{
+        storage::Atoms,
+        thread::{edit::EditBatch, TerrainThread},
+    },
 };
 
 use super::{
@@ -20,23 +23,34 @@ impl Plugin for BuildingPlugin {
 }
 
 pub fn place_atom_system(
-    mut world: ResMut<Atoms>,
+    world: Res<Atoms>,
+    thread: Res<TerrainThread>,
     player_query: Query<&LookPos, With<Player>>,
     bindings: Res<Bindings>,
     mut inputs: <bindings::Button as Binding>::Inputs<'_, '_>,
-    elements: Res<IdMap<Element>>,
     selected_element: Res<SelectedElement>,
+    mut box_corner: Local<Option<IVec3>>,
 ) {
+    let mut batch = EditBatch::default();
     let look_pos = player_query.single();
     if let Some(pos) = &look_pos.0 {
         if bindings.break_atom.just_pressed(&mut inputs) && world.contains_atom(pos.grid_pos) {
-            world.set(pos.grid_pos.as_uvec3(), elements.air());
+            batch.set_atom(pos.grid_pos.as_uvec3(), Element::AIR_ID);
         }
         let place_pos = pos.grid_pos + pos.side.normal_ivec();
         if bindings.place_atom.just_pressed(&mut inputs) && world.contains_atom(place_pos) {
-            if let Some(atom) = elements.instance_of(selected_element.0) {
-                world.set(place_pos.as_uvec3(), atom);
+            batch.set_atom(place_pos.as_uvec3(), selected_element.0);
+        }
+        if bindings.fill_box.just_pressed(&mut inputs) && world.contains_atom(place_pos) {
+            match box_corner.take() {
+                Some(corner) => batch.fill_box(
+                    corner.min(place_pos).as_uvec3(),
+                    corner.max(place_pos).as_uvec3(),
+                    selected_element.0,
+                ),
+                None => *box_corner = Some(place_pos),
             }
         }
     }
+    thread.submit_edits(batch);
 }
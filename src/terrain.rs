@@ -10,13 +10,19 @@ use bevy::prelude::*;
 use crate::atom_physics::{
     element::{Element, ElementId},
     id::IdMap,
+    reaction::Reactions,
 };
 
-use self::{color::AtomColor, storage::Atoms};
+use self::{
+    color::AtomColor,
+    storage::{Atoms, DEFAULT_SIZE},
+};
 
 pub mod change_detection;
 pub mod color;
 pub mod rendering;
+pub mod save;
+pub mod simulation;
 pub mod storage;
 pub mod thread;
 
@@ -24,15 +30,50 @@ pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((rendering::RenderingPlugin, thread::ThreadPlugin))
-            .init_resource::<Atoms>();
+        let size = app
+            .world
+            .get_resource_or_insert_with(InitialWorldSize::default)
+            .0;
+        app.add_plugins((
+            rendering::RenderingPlugin,
+            simulation::SimulationPlugin,
+            thread::ThreadPlugin,
+        ))
+        .insert_resource(Atoms::new(size));
+    }
+}
+
+/// The size of the world when the app starts.  Insert it before adding
+/// [`TerrainPlugin`] to use a size other than [`DEFAULT_SIZE`].
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct InitialWorldSize(pub UVec3);
+
+impl Default for InitialWorldSize {
+    fn default() -> Self {
+        Self(DEFAULT_SIZE)
     }
 }
 
+/// Parses a world size written like `128x48x256`.  Returns `None` if it isn't
+/// a valid world size.
+pub fn parse_size(text: &str) -> Option<UVec3> {
+    let mut axes = text.split('x').map(|axis| axis.trim().parse::<u32>());
+    let size = match (axes.next(), axes.next(), axes.next(), axes.next()) {
+        (Some(Ok(x)), Some(Ok(y)), Some(Ok(z)), None) => UVec3 { x, y, z },
+        _ => return None,
+    };
+    storage::is_valid_size(size).then_some(size)
+}
+
 #[derive(Debug)]
 pub struct AtomWorld {
     pub atoms: Atoms,
     pub elements: IdMap<Element>,
+    pub reactions: Reactions,
+    /// Seed for the simulation's random numbers.
+    pub seed: u64,
+    /// How many simulation ticks have run.
+    pub tick: u64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -244,3 +285,18 @@ impl<T> IndexMut<Opacity> for ByOpacity<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("128x48x256"), Some(UVec3::new(128, 48, 256)));
+        assert_eq!(parse_size("16x16"), None);
+        assert_eq!(parse_size("16x16x16x16"), None);
+        assert_eq!(parse_size("16x20x16"), None);
+        assert_eq!(parse_size("0x16x16"), None);
+        assert_eq!(parse_size("big"), None);
+    }
+}
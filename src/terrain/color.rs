@@ -27,6 +27,28 @@ impl AtomColor {
         Self::from_parts(y, y, y, y)
     }
 
+    /// Interpolates each channel between `self` and `other`, where a `t` of 0
+    /// is `self` and 1 is `other`.
+    pub fn mix(self, other: Self, t: f32) -> Self {
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Self::from_parts(
+            lerp(self.r, other.r),
+            lerp(self.g, other.g),
+            lerp(self.b, other.b),
+            lerp(self.a, other.a),
+        )
+    }
+
+    /// Moves the color `amount` of the way to black, keeping its alpha.
+    pub fn darken(self, amount: f32) -> Self {
+        self.mix(Self::from_parts(0, 0, 0, self.a), amount)
+    }
+
+    /// Moves the color `amount` of the way to white, keeping its alpha.
+    pub fn lighten(self, amount: f32) -> Self {
+        self.mix(Self::from_parts(0xff, 0xff, 0xff, self.a), amount)
+    }
+
     pub fn decompress(self) -> UncompressedColor {
         let [r, g, b, a] = (Color::rgba_u8(self.r, self.g, self.b, self.a)).as_rgba_f32();
         UncompressedColor([r * a, g * a, b * a, a])
@@ -10,7 +10,7 @@ use bevy::{
     },
 };
 
-use super::{storage::DEFAULT_SIZE, Atom, ByOpacity};
+use super::{storage::Atoms, Atom, ByOpacity};
 
 pub mod mesh_gen;
 
@@ -23,7 +23,8 @@ impl Plugin for RenderingPlugin {
             MaterialPlugin::<TerrainMaterial>::default(),
         ))
         .add_systems(PreStartup, create_terrain_materials_system)
-        .add_systems(Startup, create_floor_system);
+        .add_systems(Startup, create_floor_system)
+        .add_systems(Update, resize_floor_system);
     }
 }
 
@@ -80,27 +81,43 @@ fn create_terrain_materials_system(
     })
 }
 
+/// The floor under the world, which is resized to match the world.
+#[derive(Debug, Component)]
+struct Floor;
+
 /// Startup system that spawns in the floor for the world.
 fn create_floor_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     materials: Res<TerrainMaterials>,
+    world: Res<Atoms>,
 ) {
     let mesh = meshes.add(mesh_gen::cube());
-    commands.spawn(MaterialMeshBundle {
-        mesh,
-        material: materials.opaque.clone(),
-        transform: Transform {
-            translation: Vec3::new(
-                DEFAULT_SIZE.x as f32 * 0.5 - 0.5,
-                -1.0,
-                DEFAULT_SIZE.z as f32 * 0.5 - 0.5,
-            ),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::new(DEFAULT_SIZE.x as f32, 1.0, DEFAULT_SIZE.z as f32),
+    commands.spawn((
+        MaterialMeshBundle {
+            mesh,
+            material: materials.opaque.clone(),
+            transform: floor_transform(world.size()),
+            ..default()
         },
-        ..default()
-    });
+        Floor,
+    ));
+}
+
+fn resize_floor_system(world: Res<Atoms>, mut floor_query: Query<&mut Transform, With<Floor>>) {
+    if world.is_changed() {
+        for mut transform in &mut floor_query {
+            transform.set_if_neq(floor_transform(world.size()));
+        }
+    }
+}
+
+fn floor_transform(size: UVec3) -> Transform {
+    Transform {
+        translation: Vec3::new(size.x as f32 * 0.5 - 0.5, -1.0, size.z as f32 * 0.5 - 0.5),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::new(size.x as f32, 1.0, size.z as f32),
+    }
 }
 
 pub const CHUNK_SIZE: usize = 16;
@@ -117,7 +134,6 @@ pub struct ChunkData {
 #[derive(Debug, Default, Clone)]
 pub struct ChunkDataByOpacity {
     atoms: u16,
-    mesh: Option<(Entity, Handle<Mesh>)>,
 }
 
 impl ChunkData {
@@ -125,6 +141,11 @@ impl ChunkData {
         self.is_changed = true;
     }
 
+    /// Whether the chunk changed since this was last called.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.is_changed)
+    }
+
     pub fn atom_changed(&mut self, old: &Atom, new: &Atom) {
         self.is_changed = true;
         macro_rules! update_count {
@@ -141,14 +162,16 @@ impl ChunkData {
         *count = count.wrapping_add_signed(f(new) as i16 - f(old) as i16);
     }
 
-    /// Implementation detail of `Atoms::modify_all`; not intended to be used
+    /// Implementation detail of `Atoms::modify_all` and
+    /// `Atoms::load_snapshot`; not intended to be used
     /// elsewhere.
     pub(super) fn __reset_counts(&mut self) {
         self.by_opacity.opaque.atoms = 0;
         self.by_opacity.transparent.atoms = 0;
     }
 
-    /// Implementation detail of `Atoms::modify_all`; not intended to be used
+    /// Implementation detail of `Atoms::modify_all` and
+    /// `Atoms::load_snapshot`; not intended to be used
     /// elsewhere.
     pub(super) fn __add_atom(&mut self, atom: &Atom) {
         macro_rules! update_count {
@@ -2,13 +2,15 @@ use std::iter;
 
 use bevy::{
     prelude::*,
-    render::{mesh::VertexAttributeValues, primitives::Aabb, render_resource::PrimitiveTopology},
+    render::{primitives::Aabb, render_resource::PrimitiveTopology},
+    utils::HashMap,
 };
 
 use crate::terrain::{
     color::{AtomColor, UncompressedColor},
     storage::{AtomRef, Atoms, Chunk},
-    Direction, JoinFace, Opacity,
+    thread::TerrainThread,
+    ByOpacity, Direction, JoinFace, Opacity,
 };
 
 use super::{ChunkData, ChunkDataByOpacity, TerrainMaterials};
@@ -20,105 +22,148 @@ pub struct MeshGenPlugin;
 impl Plugin for MeshGenPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(inspector::InspectorPlugin)
-            .add_systems(Update, generate_chunk_meshes_system);
+            .init_resource::<ChunkMeshes>()
+            .add_systems(Update, upload_chunk_meshes_system);
     }
 }
 
-fn generate_chunk_meshes_system(
+/// The entities and meshes spawned for each chunk, by the chunk's position.
+#[derive(Debug, Default, Resource)]
+struct ChunkMeshes(HashMap<UVec3, ByOpacity<Option<MeshEntity>>>);
+
+type MeshEntity = (Entity, Handle<Mesh>);
+
+/// Vertex buffers for a single chunk mesh, generated on the terrain thread.
+#[derive(Debug, Clone, Default)]
+pub struct MeshBuffers {
+    position: Vec<[f32; 3]>,
+    color: Vec<[f32; 4]>,
+    aabb: Aabb,
+}
+
+/// The new meshes for a chunk that changed.
+#[derive(Debug, Clone)]
+pub struct MeshUpdate {
+    pos: UVec3,
+    /// `None` if the chunk has no atoms with that opacity.
+    meshes: ByOpacity<Option<MeshBuffers>>,
+}
+
+impl MeshUpdate {
+    /// Removes the meshes for the chunk at `pos`, such as when it's no longer
+    /// part of the world.
+    pub fn empty(pos: UVec3) -> Self {
+        Self {
+            pos,
+            meshes: ByOpacity::default(),
+        }
+    }
+}
+
+/// Generates the meshes for the chunk at `pos`, measured in atoms.
+pub fn generate_chunk_meshes(world: &Atoms, pos: UVec3) -> MeshUpdate {
+    let (chunk, chunk_data) = world.chunk(pos);
+    let mut meshes = ByOpacity::default();
+    for opacity in Opacity::VARIANTS {
+        meshes[opacity] = generate_chunk_mesh(chunk.clone(), chunk_data, pos, opacity);
+    }
+    MeshUpdate { pos, meshes }
+}
+
+fn generate_chunk_mesh(
+    chunk: Chunk,
+    data: &ChunkData,
+    pos: UVec3,
+    opacity: Opacity,
+) -> Option<MeshBuffers> {
+    let data = &data.by_opacity[opacity];
+    if data.atoms == 0 {
+        return None;
+    }
+
+    let mut buffers = MeshBuffers::default();
+    let builder = MeshBuilder {
+        position: &mut buffers.position,
+        color: &mut buffers.color,
+    };
+    match opacity {
+        Opacity::Opaque => generate_chunk_mesh_opaque(chunk, pos, data, builder),
+        Opacity::Transparent => generate_chunk_mesh_transparent(chunk, pos, data, builder),
+    }
+    buffers.aabb = compute_aabb(&buffers.position);
+    Some(buffers)
+}
+
+fn compute_aabb(position: &[[f32; 3]]) -> Aabb {
+    let (min, max) = position.iter().map(|&p| Vec3::from(p)).fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), p| (min.min(p), max.max(p)),
+    );
+    match min.cmple(max).all() {
+        true => Aabb::from_min_max(min, max),
+        false => Aabb::default(),
+    }
+}
+
+/// Uploads the meshes generated by the terrain thread, spawning and despawning
+/// chunk mesh entities as needed.
+fn upload_chunk_meshes_system(
     mut commands: Commands,
-    mut aabb_query: Query<&mut Aabb>,
-    mut world: ResMut<Atoms>,
+    thread: Res<TerrainThread>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
     mut meshes: ResMut<Assets<Mesh>>,
     materials: Res<TerrainMaterials>,
 ) {
-    for (pos, chunk, chunk_data) in world.chunks() {
-        if chunk_data.is_changed {
-            chunk_data.is_changed = false;
-            modify_chunk_meshes(
+    for MeshUpdate { pos, meshes: new } in thread.mesh_updates() {
+        let chunk = chunk_meshes.0.entry(pos).or_default();
+        for (opacity, buffers) in [
+            (Opacity::Opaque, new.opaque),
+            (Opacity::Transparent, new.transparent),
+        ] {
+            upload_chunk_mesh(
                 &mut commands,
-                &mut aabb_query,
                 &mut meshes,
                 &materials,
-                chunk,
-                chunk_data,
+                &mut chunk[opacity],
+                buffers,
                 pos,
+                opacity,
             );
         }
+        if chunk.opaque.is_none() && chunk.transparent.is_none() {
+            chunk_meshes.0.remove(&pos);
+        }
     }
 }
 
-fn modify_chunk_meshes(
-    commands: &mut Commands,
-    aabb_query: &mut Query<&mut Aabb>,
-    meshes: &mut Assets<Mesh>,
-    materials: &TerrainMaterials,
-    chunk: Chunk,
-    data: &mut ChunkData,
-    pos: UVec3,
-) {
-    for typ in Opacity::VARIANTS {
-        modify_chunk_mesh(
-            commands,
-            aabb_query,
-            meshes,
-            materials,
-            chunk.clone(),
-            data,
-            pos,
-            typ,
-        );
-    }
-}
-
-#[allow(clippy::too_many_arguments)]
-fn modify_chunk_mesh(
+fn upload_chunk_mesh(
     commands: &mut Commands,
-    aabb_query: &mut Query<&mut Aabb>,
     meshes: &mut Assets<Mesh>,
     materials: &TerrainMaterials,
-    chunk: Chunk,
-    data: &mut ChunkData,
+    chunk_mesh: &mut Option<MeshEntity>,
+    buffers: Option<MeshBuffers>,
     pos: UVec3,
     opacity: Opacity,
 ) {
-    let data = &mut data.by_opacity[opacity];
-    if data.atoms > 0 {
-        let (entity, mesh) = get_entity_and_mesh(commands, meshes, materials, data, pos, opacity);
-        let mut builder = MeshBuilder::extract(mesh);
-        builder.clear();
-
-        match opacity {
-            Opacity::Opaque => generate_chunk_mesh_opaque(chunk, pos, data, builder),
-            Opacity::Transparent => generate_chunk_mesh_transparent(chunk, pos, data, builder),
+    match buffers {
+        Some(buffers) => {
+            let (entity, mesh) = chunk_mesh
+                .get_or_insert_with(|| init_chunk_mesh(commands, meshes, materials, pos, opacity));
+            let mesh = meshes.get_mut(mesh).unwrap();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, buffers.position);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, buffers.color);
+            commands.entity(*entity).insert(buffers.aabb);
         }
-
-        let aabb = mesh.compute_aabb().unwrap_or_default();
-        if let Ok(mut mesh_aabb) = aabb_query.get_mut(entity) {
-            *mesh_aabb = aabb;
-        } else {
-            commands.entity(entity).insert(aabb);
+        None => {
+            if let Some((entity, _)) = chunk_mesh.take() {
+                commands.entity(entity).despawn();
+                // No need to clean up mesh because it will be removed when its
+                // handle is dropped.
+            }
         }
-    } else if let Some((entity, _)) = data.mesh.take() {
-        commands.entity(entity).despawn();
-        // No need to clean up mesh because it will be removed when its handle
-        // is dropped.
     }
 }
 
-fn get_entity_and_mesh<'m>(
-    commands: &mut Commands,
-    meshes: &'m mut Assets<Mesh>,
-    materials: &TerrainMaterials,
-    data: &mut ChunkDataByOpacity,
-    pos: UVec3,
-    opacity: Opacity,
-) -> (Entity, &'m mut Mesh) {
-    let (entity, mesh) = data
-        .mesh
-        .get_or_insert_with(|| init_chunk_mesh(commands, meshes, materials, pos, opacity));
-    (*entity, meshes.get_mut(mesh).unwrap())
-}
-
 fn generate_chunk_mesh_opaque(
     chunk: Chunk,
     pos: UVec3,
@@ -185,7 +230,7 @@ fn init_chunk_mesh(
     materials: &TerrainMaterials,
     pos: UVec3,
     opacity: Opacity,
-) -> (Entity, Handle<Mesh>) {
+) -> MeshEntity {
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
     mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, Vec::<[f32; 4]>::new());
@@ -214,39 +259,6 @@ struct MeshBuilder<'a> {
 }
 
 impl<'a> MeshBuilder<'a> {
-    fn extract(mesh: &mut Mesh) -> MeshBuilder<'_> {
-        let mut position = None;
-        let mut color = None;
-
-        for (id, values) in mesh.attributes_mut() {
-            if id == Mesh::ATTRIBUTE_POSITION.id {
-                let VertexAttributeValues::Float32x3(p) = values else { panic!(
-                   "position should be `Float32x3` but is `{}``",
-                    values.enum_variant_name()
-                ) };
-                position = Some(p);
-            } else if id == Mesh::ATTRIBUTE_COLOR.id {
-                let VertexAttributeValues::Float32x4(p) = values else { panic!(
-                    "color should be `Float32x4` but is `{}``",
-                    values.enum_variant_name()
-                ) };
-                color = Some(p);
-            } else {
-                panic!("Unexpected mesh attribute: {id:?}")
-            }
-        }
-
-        let position = position.expect("Terrain mesh missing position");
-        let color = color.expect("Terrain mesh missing color");
-
-        MeshBuilder { position, color }
-    }
-
-    fn clear(&mut self) {
-        self.position.clear();
-        self.color.clear();
-    }
-
     fn reserve(&mut self, faces: usize) {
         // 6 vertices per face.
         let num_vertices = faces * 6;
@@ -304,3 +316,28 @@ pub fn cube() -> Mesh {
 
     mesh
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::Atom;
+
+    use super::*;
+
+    #[test]
+    fn single_atom_mesh() {
+        let mut world = Atoms::default();
+        world.set(
+            UVec3::new(17, 1, 2),
+            Atom {
+                color: AtomColor::WHITE,
+                ..Atom::AIR
+            },
+        );
+
+        let update = generate_chunk_meshes(&world, UVec3::new(16, 0, 0));
+        assert!(update.meshes.transparent.is_none());
+        let mesh = update.meshes.opaque.as_ref().unwrap();
+        assert_eq!(mesh.position.len(), 36);
+        assert_eq!(mesh.aabb.center, Vec3::new(1.0, 1.0, 2.0).into());
+    }
+}
@@ -0,0 +1,632 @@
+//! Saving worlds to files and loading them back.
+//!
+//! A save starts with [`MAGIC`] and the format version, followed by the world
+//! size, the seed and tick of the simulation, and how many chunks were saved.
+//! Chunks that were never changed are all air, so they aren't saved.  Each
+//! chunk is its position, measured in chunks, then the names of the elements
+//! it uses, then its atoms in the same order as [`Atoms::chunk`] as runs of
+//! identical atoms.  Each run is its length as a varint, then the atom's index
+//! into the chunk's names and its temperature.
+//!
+//! Version 2 is the same, but without the seed and tick, so the current seed
+//! is kept and the tick starts from 0.  Version 1 doesn't have them either,
+//! and saves every atom in the world instead of each chunk, ordered by y, then
+//! z, then x, after a single list of names.
+//!
+//! Only the element and temperature of each atom are saved; everything else
+//! comes from the element when loading, so element ids are remapped by name
+//! the same way as when a set is reloaded.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    iter,
+    path::Path,
+};
+
+use bevy::prelude::*;
+
+use crate::atom_physics::{
+    element::{Element, ElementId},
+    id::IdMap,
+};
+
+use super::{
+    rendering::CHUNK_SIZE,
+    storage::{self, Atoms, CHUNK_VOLUME},
+    Atom, AtomWorld,
+};
+
+/// Identifies a file as a saved world.
+pub const MAGIC: [u8; 4] = *b"PSWD";
+
+/// The current version of the format.  Saves from earlier versions can still
+/// be loaded.
+pub const VERSION: u16 = 3;
+
+/// Longer names are assumed to mean the save is corrupt, rather than
+/// allocating however much memory the save asks for.
+const MAX_NAME_LENGTH: u64 = 1 << 16;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    NotASave,
+    UnsupportedVersion(u16),
+    InvalidSize(UVec3),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::NotASave => write!(f, "File is not a saved world"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Save is from version {version} of the format, but only versions up to {VERSION} are supported"
+            ),
+            LoadError::InvalidSize(size) => {
+                write!(f, "Saved world is {size} atoms, which isn't a valid size")
+            }
+            LoadError::Corrupt(reason) => write!(f, "Save is corrupt: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => LoadError::Corrupt("file ends early"),
+            _ => LoadError::Io(e),
+        }
+    }
+}
+
+/// What happened while loading a world that didn't stop it from loading.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadSummary {
+    /// Elements in the save that aren't in the current set.  Their atoms are
+    /// replaced with air.
+    pub missing_elements: Vec<String>,
+}
+
+/// Where the world is saved to by default.
+pub const DEFAULT_PATH: &str = "saves/world.pswd";
+
+/// Saves the world to a file, logging whether it worked.
+pub fn save_to_file(world: &AtomWorld, path: &Path) {
+    let result = (|| {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        save_world(world, BufWriter::new(File::create(path)?))
+    })();
+    match result {
+        Ok(()) => info!("Saved world to {}", path.display()),
+        Err(e) => error!("Unable to save world to {}: {e}", path.display()),
+    }
+}
+
+/// Loads the world from a file, logging whether it worked.  Returns whether
+/// the world was replaced.
+pub fn load_from_file(world: &mut AtomWorld, path: &Path) -> bool {
+    let result = File::open(path)
+        .map_err(LoadError::from)
+        .and_then(|file| load_world(world, BufReader::new(file)));
+    match result {
+        Ok(summary) => {
+            info!("Loaded world from {}", path.display());
+            for name in summary.missing_elements {
+                warn!("Element {name} isn't in the current set; replaced it with air");
+            }
+            true
+        }
+        Err(e) => {
+            error!("Unable to load world from {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+pub fn save_world(world: &AtomWorld, mut writer: impl Write) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+
+    let size = world.atoms.size();
+    for axis in size.to_array() {
+        writer.write_all(&axis.to_le_bytes())?;
+    }
+    writer.write_all(&world.seed.to_le_bytes())?;
+    writer.write_all(&world.tick.to_le_bytes())?;
+
+    let mut chunks: Vec<_> = world.atoms.chunk_positions().collect();
+    chunks.sort_unstable_by_key(|pos| (pos.y, pos.z, pos.x));
+    write_varint(&mut writer, chunks.len() as u64)?;
+    for pos in chunks {
+        write_chunk(world, pos, &mut writer)?;
+    }
+    writer.flush()
+}
+
+/// Writes the chunk at `pos`, measured in atoms, with the names of only the
+/// elements it uses.  This is also how chunks are stored when they're
+/// unloaded.
+pub fn write_chunk(world: &AtomWorld, pos: UVec3, mut writer: impl Write) -> io::Result<()> {
+    for axis in (pos / CHUNK_SIZE as u32).to_array() {
+        write_varint(&mut writer, u64::from(axis))?;
+    }
+
+    let (chunk, _) = world.atoms.chunk(pos);
+    let mut used = Vec::new();
+    for atom in chunk.clone() {
+        if !used.contains(&atom.element) {
+            used.push(atom.element);
+        }
+    }
+    write_varint(&mut writer, used.len() as u64)?;
+    for &id in &used {
+        let (name, _) = world.elements.get_full(id).unwrap();
+        write_varint(&mut writer, name.len() as u64)?;
+        writer.write_all(name.as_bytes())?;
+    }
+
+    // Temperatures are compared by their bits so runs are only merged when
+    // the atoms load back exactly the same.
+    let mut run: Option<(u64, ElementId, u32)> = None;
+    for (atom, temperature) in chunk.zip(world.atoms.chunk_temperatures(pos)) {
+        let local_id = used.iter().position(|&id| id == atom.element).unwrap() as ElementId;
+        let atom = (local_id, temperature.to_bits());
+        match &mut run {
+            Some((len, element, temperature)) if (*element, *temperature) == atom => *len += 1,
+            _ => {
+                if let Some(run) = run.replace((1, atom.0, atom.1)) {
+                    write_run(&mut writer, run)?;
+                }
+            }
+        }
+    }
+    if let Some(run) = run {
+        write_run(&mut writer, run)?;
+    }
+    Ok(())
+}
+
+/// A chunk read by [`read_chunk`].
+#[derive(Debug, Clone)]
+pub struct SavedChunk {
+    /// Measured in atoms.
+    pub pos: UVec3,
+    atoms: Vec<Atom>,
+    temperatures: Vec<f32>,
+}
+
+impl SavedChunk {
+    pub fn insert_into(self, atoms: &mut Atoms) {
+        atoms.insert_chunk(self.pos, self.atoms, Some(self.temperatures));
+    }
+}
+
+/// Reads a chunk written by [`write_chunk`].  Elements that aren't in the
+/// current set are replaced with air and added to `summary`.
+pub fn read_chunk(
+    elements: &IdMap<Element>,
+    mut reader: impl Read,
+    summary: &mut LoadSummary,
+) -> Result<SavedChunk, LoadError> {
+    let mut pos = [0; 3];
+    for axis in &mut pos {
+        *axis = u32::try_from(read_varint(&mut reader)?)
+            .ok()
+            .and_then(|axis| axis.checked_mul(CHUNK_SIZE as u32))
+            .ok_or(LoadError::Corrupt("chunk position is too big"))?;
+    }
+
+    let remap = read_names(elements, &mut reader, summary)?;
+    let mut chunk = SavedChunk {
+        pos: UVec3::from_array(pos),
+        atoms: Vec::with_capacity(CHUNK_VOLUME),
+        temperatures: Vec::with_capacity(CHUNK_VOLUME),
+    };
+    read_runs(
+        elements,
+        &remap,
+        &mut reader,
+        CHUNK_VOLUME as u64,
+        |atom, temperature, len| {
+            chunk.atoms.extend(iter::repeat_n(atom, len));
+            chunk.temperatures.extend(iter::repeat_n(temperature, len));
+        },
+    )?;
+    Ok(chunk)
+}
+
+/// Replaces the atoms in the world with ones from a save, resizing the world
+/// to match the save, and restores the seed and tick of the simulation.
+pub fn load_world(world: &mut AtomWorld, mut reader: impl Read) -> Result<LoadSummary, LoadError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(LoadError::NotASave);
+    }
+    let version = u16::from_le_bytes(read_array(&mut reader)?);
+    if !(1..=VERSION).contains(&version) {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let mut size = [0; 3];
+    for axis in &mut size {
+        *axis = u32::from_le_bytes(read_array(&mut reader)?);
+    }
+    let size = UVec3::from_array(size);
+    if !storage::is_valid_size(size) {
+        return Err(LoadError::InvalidSize(size));
+    }
+
+    let mut summary = LoadSummary::default();
+    if version == 1 {
+        load_atoms_v1(world, reader, size, &mut summary)?;
+        world.tick = 0;
+    } else {
+        let (seed, tick) = if version >= 3 {
+            let seed = u64::from_le_bytes(read_array(&mut reader)?);
+            (seed, u64::from_le_bytes(read_array(&mut reader)?))
+        } else {
+            (world.seed, 0)
+        };
+        let chunk_count = read_varint(&mut reader)?;
+        let max_chunks = (size / CHUNK_SIZE as u32)
+            .to_array()
+            .map(u64::from)
+            .iter()
+            .product();
+        if chunk_count > max_chunks {
+            return Err(LoadError::Corrupt("more chunks than fit in the world"));
+        }
+        let mut chunks = Vec::new();
+        for _ in 0..chunk_count {
+            let chunk = read_chunk(&world.elements, &mut reader, &mut summary)?;
+            if chunk.pos.cmpge(size).any() {
+                return Err(LoadError::Corrupt("chunk is outside of the world"));
+            }
+            chunks.push(chunk);
+        }
+
+        world.atoms.reset(size);
+        for chunk in chunks {
+            chunk.insert_into(&mut world.atoms);
+        }
+        world.seed = seed;
+        world.tick = tick;
+    }
+    world.atoms.wake_all();
+    Ok(summary)
+}
+
+/// Loads the atoms from a version 1 save, which stores every atom in the world
+/// rather than each chunk.
+fn load_atoms_v1(
+    world: &mut AtomWorld,
+    mut reader: impl Read,
+    size: UVec3,
+    summary: &mut LoadSummary,
+) -> Result<(), LoadError> {
+    let remap = read_names(&world.elements, &mut reader, summary)?;
+    let mut positions = positions(size);
+    let mut atoms = Vec::new();
+    let remaining = u64::from(size.x) * u64::from(size.y) * u64::from(size.z);
+    read_runs(
+        &world.elements,
+        &remap,
+        reader,
+        remaining,
+        |atom, temperature, len| {
+            for pos in positions.by_ref().take(len) {
+                atoms.push((pos, atom.clone(), temperature));
+            }
+        },
+    )?;
+
+    world.atoms.reset(size);
+    for &(pos, _, temperature) in &atoms {
+        world.atoms.set_temperature(pos, temperature);
+    }
+    world
+        .atoms
+        .set_many(atoms.into_iter().map(|(pos, atom, _)| (pos, atom)));
+    Ok(())
+}
+
+/// Reads the names of the elements used by what follows, returning the id of
+/// each in the current set.
+fn read_names(
+    elements: &IdMap<Element>,
+    mut reader: impl Read,
+    summary: &mut LoadSummary,
+) -> Result<Vec<ElementId>, LoadError> {
+    let element_count = read_varint(&mut reader)?;
+    let mut remap = Vec::new();
+    for _ in 0..element_count {
+        let len = read_varint(&mut reader)?;
+        if len > MAX_NAME_LENGTH {
+            return Err(LoadError::Corrupt("element name is too long"));
+        }
+        let mut name = vec![0; len as usize];
+        reader.read_exact(&mut name)?;
+        let name =
+            String::from_utf8(name).map_err(|_| LoadError::Corrupt("invalid element name"))?;
+        match elements.get_full_by_name(&name) {
+            Some((id, _)) => remap.push(id),
+            None => {
+                remap.push(Element::AIR_ID);
+                if !summary.missing_elements.contains(&name) {
+                    summary.missing_elements.push(name);
+                }
+            }
+        }
+    }
+    Ok(remap)
+}
+
+/// Reads runs until they cover `remaining` atoms, calling `f` with the atom,
+/// temperature and length of each.
+fn read_runs(
+    elements: &IdMap<Element>,
+    remap: &[ElementId],
+    mut reader: impl Read,
+    mut remaining: u64,
+    mut f: impl FnMut(Atom, f32, usize),
+) -> Result<(), LoadError> {
+    while remaining > 0 {
+        let len = read_varint(&mut reader)?;
+        if len == 0 || len > remaining {
+            return Err(LoadError::Corrupt("run doesn't fit in the world"));
+        }
+        remaining -= len;
+        let [element] = read_array(&mut reader)?;
+        let temperature = f32::from_le_bytes(read_array(&mut reader)?);
+        let element = *remap
+            .get(usize::from(element))
+            .ok_or(LoadError::Corrupt("unknown element id"))?;
+        f(
+            elements.instance_of(element).unwrap(),
+            temperature,
+            len as usize,
+        );
+    }
+    Ok(())
+}
+
+/// Every position in a world of the given size, in the order atoms are saved
+/// in version 1.
+fn positions(size: UVec3) -> impl Iterator<Item = UVec3> {
+    (0..size.y).flat_map(move |y| {
+        (0..size.z).flat_map(move |z| (0..size.x).map(move |x| UVec3 { x, y, z }))
+    })
+}
+
+fn write_run(
+    writer: &mut impl Write,
+    (len, element, temperature): (u64, ElementId, u32),
+) -> io::Result<()> {
+    write_varint(writer, len)?;
+    writer.write_all(&[element])?;
+    writer.write_all(&temperature.to_le_bytes())
+}
+
+/// Writes 7 bits at a time, lowest first, with the top bit set on every byte
+/// but the last.
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64, LoadError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let [byte] = read_array(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(LoadError::Corrupt("varint is too long"))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        atom_physics::{id::MappedToId, reaction::Reactions},
+        terrain::{color::AtomColor, storage::Atoms},
+    };
+
+    use super::*;
+
+    fn world(elements: &[&str]) -> AtomWorld {
+        let mut world = AtomWorld {
+            atoms: Atoms::default(),
+            elements: Element::create_map(),
+            reactions: Reactions::default(),
+            seed: 0,
+            tick: 0,
+        };
+        for (i, name) in elements.iter().enumerate() {
+            let element = Element {
+                color: AtomColor::from_grey(40 * i as u8 + 40),
+                ..Default::default()
+            };
+            world.elements.insert(*name, element).unwrap();
+        }
+        world
+    }
+
+    fn place(world: &mut AtomWorld, pos: UVec3, name: &str, temperature: f32) {
+        let (id, _) = world.elements.get_full_by_name(name).unwrap();
+        world
+            .atoms
+            .set(pos, world.elements.instance_of(id).unwrap());
+        world.atoms.set_temperature(pos, temperature);
+    }
+
+    fn saved(world: &AtomWorld) -> Vec<u8> {
+        let mut save = Vec::new();
+        save_world(world, &mut save).unwrap();
+        save
+    }
+
+    #[test]
+    fn round_trip_remaps_elements() {
+        let mut world = world(&["Stone", "Sand"]);
+        for x in 0..32 {
+            place(&mut world, UVec3::new(x, 0, 4), "Stone", 20.0);
+        }
+        place(&mut world, UVec3::new(3, 1, 4), "Sand", -5.5);
+        let save = saved(&world);
+        // Runs keep the save much smaller than the world.
+        assert!(save.len() < 200, "save is {} bytes", save.len());
+
+        let mut loaded = self::world(&["Sand", "Stone"]);
+        let summary = load_world(&mut loaded, &save[..]).unwrap();
+        assert!(summary.missing_elements.is_empty());
+        let element = |world: &AtomWorld, pos| {
+            let id = world.atoms[pos].element;
+            world.elements.get_full(id).unwrap().0.to_owned()
+        };
+        for y in 0..3 {
+            for x in 0..40 {
+                let pos = UVec3::new(x, y, 4);
+                assert_eq!(element(&world, pos), element(&loaded, pos));
+                assert_eq!(world.atoms.temperature(pos), loaded.atoms.temperature(pos));
+            }
+        }
+        assert_eq!(
+            loaded.atoms[UVec3::new(3, 1, 4)].color,
+            AtomColor::from_grey(40)
+        );
+    }
+
+    #[test]
+    fn missing_elements_become_air() {
+        let mut world = world(&["Stone", "Gold"]);
+        place(&mut world, UVec3::new(1, 2, 3), "Gold", 20.0);
+        let mut loaded = self::world(&["Stone"]);
+        let summary = load_world(&mut loaded, &saved(&world)[..]).unwrap();
+        assert_eq!(summary.missing_elements, ["Gold"]);
+        assert_eq!(loaded.atoms[UVec3::new(1, 2, 3)].element, Element::AIR_ID);
+    }
+
+    #[test]
+    fn loading_resizes_world() {
+        let mut world = world(&["Stone"]);
+        world.atoms = Atoms::new(UVec3::new(32, 16, 48));
+        place(&mut world, UVec3::new(31, 15, 47), "Stone", 20.0);
+        let mut loaded = self::world(&["Stone"]);
+        load_world(&mut loaded, &saved(&world)[..]).unwrap();
+        assert_eq!(loaded.atoms.size(), UVec3::new(32, 16, 48));
+        assert_ne!(
+            loaded.atoms[UVec3::new(31, 15, 47)].element,
+            Element::AIR_ID
+        );
+    }
+
+    #[test]
+    fn round_trip_keeps_seed_and_tick() {
+        let mut world = world(&[]);
+        world.seed = 0x1234_5678_9abc;
+        world.tick = 4242;
+        let mut loaded = self::world(&[]);
+        loaded.tick = 7;
+        load_world(&mut loaded, &saved(&world)[..]).unwrap();
+        assert_eq!(loaded.seed, 0x1234_5678_9abc);
+        assert_eq!(loaded.tick, 4242);
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let mut save = MAGIC.to_vec();
+        save.extend(1u16.to_le_bytes());
+        for axis in [16u32; 3] {
+            save.extend(axis.to_le_bytes());
+        }
+        // One name, then a single run of every atom.
+        save.extend([1, 5]);
+        save.extend(b"Stone");
+        write_varint(&mut save, 16 * 16 * 16).unwrap();
+        save.push(0);
+        save.extend(25.0f32.to_le_bytes());
+
+        let mut loaded = self::world(&["Stone"]);
+        loaded.seed = 99;
+        loaded.tick = 7;
+        let summary = load_world(&mut loaded, &save[..]).unwrap();
+        assert!(summary.missing_elements.is_empty());
+        assert_eq!(loaded.atoms.size(), UVec3::splat(16));
+        assert_ne!(
+            loaded.atoms[UVec3::new(15, 15, 15)].element,
+            Element::AIR_ID
+        );
+        assert_eq!(loaded.atoms.temperature(UVec3::new(15, 15, 15)), 25.0);
+        assert_eq!((loaded.seed, loaded.tick), (99, 0));
+    }
+
+    #[test]
+    fn loads_version_2() {
+        let mut world = world(&["Stone"]);
+        world.seed = 99;
+        place(&mut world, UVec3::new(1, 2, 3), "Stone", 30.0);
+        // Version 2 is the same as version 3 without the seed and tick, which
+        // come right after the size.
+        let mut save = saved(&world);
+        save[4..6].copy_from_slice(&2u16.to_le_bytes());
+        save.drain(18..34);
+
+        let mut loaded = self::world(&["Stone"]);
+        loaded.seed = 5;
+        loaded.tick = 7;
+        load_world(&mut loaded, &save[..]).unwrap();
+        assert_ne!(loaded.atoms[UVec3::new(1, 2, 3)].element, Element::AIR_ID);
+        assert_eq!(loaded.atoms.temperature(UVec3::new(1, 2, 3)), 30.0);
+        assert_eq!((loaded.seed, loaded.tick), (5, 0));
+    }
+
+    #[test]
+    fn rejects_bad_saves() {
+        let mut world = world(&[]);
+        let save = saved(&world);
+
+        let mut other_version = save.clone();
+        other_version[4] = 4;
+        assert!(matches!(
+            load_world(&mut world, &other_version[..]),
+            Err(LoadError::UnsupportedVersion(4))
+        ));
+        assert!(matches!(
+            load_world(&mut world, &b"not a save"[..]),
+            Err(LoadError::NotASave)
+        ));
+        assert!(matches!(
+            load_world(&mut world, &save[..save.len() - 1]),
+            Err(LoadError::Corrupt(_))
+        ));
+
+        let mut bad_size = save.clone();
+        bad_size[6] = 17;
+        assert!(matches!(
+            load_world(&mut world, &bad_size[..]),
+            Err(LoadError::InvalidSize(_))
+        ));
+    }
+}
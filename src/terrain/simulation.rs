@@ -0,0 +1,868 @@
+//! Fixed-rate cellular automaton update, run on the terrain thread.
+
+use std::{
+    num::NonZeroUsize,
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::atom_physics::{
+    element::{Element, State},
+    id::IdMap,
+    reaction::{Reaction, Reactions},
+    rule::{Replacement, Rule},
+};
+
+use self::{
+    chunk_update::{Changes, ChunkUpdate},
+    rng::{Salt, TickRng},
+};
+
+use super::{rendering::CHUNK_SIZE, storage::Atoms, AtomWorld};
+
+mod chunk_update;
+mod inspector;
+pub mod rng;
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(inspector::InspectorPlugin);
+    }
+}
+
+/// Ticks per second the simulation runs at when the terrain thread starts.
+pub const DEFAULT_TICK_RATE: f32 = 20.0;
+
+/// How many ticks the simulation can fall behind before it stops trying to
+/// catch up.
+const MAX_TICKS_BEHIND: u32 = 4;
+
+/// Decides when the terrain thread should run simulation ticks.
+#[derive(Debug, Clone)]
+pub struct SimulationClock {
+    tick_length: Duration,
+    paused: bool,
+    queued_steps: u32,
+    next_tick: Instant,
+}
+
+impl SimulationClock {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            tick_length: Duration::from_secs_f32(1.0 / DEFAULT_TICK_RATE),
+            paused: false,
+            queued_steps: 0,
+            next_tick: now,
+        }
+    }
+
+    /// Sets how many ticks should run per second.  Rates that are not
+    /// positive are ignored.
+    pub fn set_tick_rate(&mut self, tick_rate: f32) {
+        if tick_rate > 0.0 && tick_rate.is_finite() {
+            self.tick_length = Duration::from_secs_f32(1.0 / tick_rate);
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool, now: Instant) {
+        if self.paused && !paused {
+            // Don't try to run every tick that would have happened while
+            // paused.
+            self.next_tick = now;
+        }
+        self.paused = paused;
+    }
+
+    /// Runs one more tick as soon as possible, even if paused.
+    pub fn queue_step(&mut self) {
+        self.queued_steps += 1;
+    }
+
+    /// How long until the next tick should run, or `None` if no ticks will run
+    /// until something changes.
+    pub fn time_until_tick(&self, now: Instant) -> Option<Duration> {
+        if self.queued_steps > 0 {
+            Some(Duration::ZERO)
+        } else if self.paused {
+            None
+        } else {
+            Some(self.next_tick.saturating_duration_since(now))
+        }
+    }
+
+    /// Returns `true` and advances the clock if a tick should run at `now`.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if self.queued_steps > 0 {
+            self.queued_steps -= 1;
+            true
+        } else if !self.paused && self.next_tick <= now {
+            self.next_tick += self.tick_length;
+            if self.next_tick + self.tick_length * MAX_TICKS_BEHIND < now {
+                self.next_tick = now;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Heat flows smaller than this don't keep chunks awake, so chunks can sleep
+/// once their temperature has mostly evened out.
+const MIN_WAKING_FLOW: f32 = 0.01;
+
+/// Advances the world by one tick, using every core.  Only chunks where
+/// something changed recently, and their neighbours, are simulated.
+pub fn step(world: &mut AtomWorld) {
+    static WORKER_THREADS: OnceLock<usize> = OnceLock::new();
+    let threads = *WORKER_THREADS
+        .get_or_init(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+    step_with_threads(world, threads);
+}
+
+/// Advances the world by one tick, updating chunks on up to `threads` threads
+/// at once.  The result doesn't depend on how many threads are used.
+///
+/// Chunks are updated in eight phases, with each phase updating every eighth
+/// chunk in a 2×2×2 checkerboard pattern.  Updating an atom never reaches
+/// more than one atom away, so chunks in the same phase never read or write
+/// the same positions and can be updated at the same time.
+pub fn step_with_threads(world: &mut AtomWorld, threads: usize) {
+    let active = world.atoms.active_chunks();
+    let mut phases: [Vec<UVec3>; 8] = Default::default();
+    for &chunk in active.chunks() {
+        let parity = chunk % 2;
+        phases[(parity.x | parity.y << 1 | parity.z << 2) as usize].push(chunk);
+    }
+
+    let AtomWorld {
+        atoms,
+        elements,
+        reactions,
+        seed,
+        tick,
+    } = world;
+    let rng = TickRng::new(*seed, *tick);
+    // Positions of atoms that have already been updated this tick, so nothing
+    // moves twice.
+    let mut moved = HashSet::new();
+    for phase in &phases {
+        run_phase(atoms, phase, &mut moved, threads, |update, chunk| {
+            for pos in chunk_positions(chunk) {
+                if !update.has_moved(pos) {
+                    update_atom(update, elements, reactions, rng, pos);
+                }
+            }
+        });
+    }
+    for phase in &phases {
+        run_phase(atoms, phase, &mut moved, threads, |update, chunk| {
+            conduct_heat(update, elements, chunk)
+        });
+    }
+    *tick += 1;
+}
+
+/// Runs `update_chunk` for every chunk in the phase, then applies the changes.
+fn run_phase(
+    atoms: &mut Atoms,
+    chunks: &[UVec3],
+    moved: &mut HashSet<UVec3>,
+    threads: usize,
+    update_chunk: impl Fn(&mut ChunkUpdate, UVec3) + Sync,
+) {
+    let update_chunks = |chunks: &[UVec3]| -> Vec<Changes> {
+        chunks
+            .iter()
+            .map(|&chunk| {
+                let mut update = ChunkUpdate::new(atoms, moved);
+                update_chunk(&mut update, chunk);
+                update.finish()
+            })
+            .collect()
+    };
+
+    let changes = if threads <= 1 || chunks.len() <= 1 {
+        update_chunks(chunks)
+    } else {
+        let chunks_per_thread = chunks.len().div_ceil(threads);
+        thread::scope(|scope| {
+            let workers: Vec<_> = chunks
+                .chunks(chunks_per_thread)
+                .map(|chunks| scope.spawn(|| update_chunks(chunks)))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Simulation worker panicked"))
+                .collect()
+        })
+    };
+
+    for changes in changes {
+        changes.apply(atoms, moved);
+    }
+}
+
+/// Every position in a chunk, from the bottom of the chunk to the top.
+fn chunk_positions(chunk: UVec3) -> impl Iterator<Item = UVec3> {
+    let size = CHUNK_SIZE as u32;
+    let offset = chunk * size;
+    (0..size).flat_map(move |y| {
+        (0..size).flat_map(move |z| (0..size).map(move |x| offset + UVec3 { x, y, z }))
+    })
+}
+
+/// Evens out temperature between every pair of neighbouring atoms.  Heat
+/// flowing out of one atom always flows into another, so the total
+/// temperature of the world is unchanged.
+fn conduct_heat(update: &mut ChunkUpdate, elements: &IdMap<Element>, chunk: UVec3) {
+    let conductivity = |update: &ChunkUpdate, pos: UVec3| {
+        elements
+            .get(update[pos].element)
+            .map_or(0.0, |element| element.conductivity)
+    };
+    for pos in chunk_positions(chunk) {
+        let own_conductivity = conductivity(update, pos);
+        if own_conductivity == 0.0 {
+            continue;
+        }
+        // Only the positive directions, so each pair is visited once.
+        for offset in [UVec3::X, UVec3::Y, UVec3::Z] {
+            let neighbor = pos + offset;
+            if !update.contains_atom(neighbor) {
+                continue;
+            }
+            // Divided by the number of neighbours so an atom can never give
+            // away more heat than the difference.
+            let rate = own_conductivity.min(conductivity(update, neighbor)) / 6.0;
+            let flow = rate * (update.temperature(neighbor) - update.temperature(pos));
+            if flow != 0.0 {
+                update.set_temperature(pos, update.temperature(pos) + flow);
+                update.set_temperature(neighbor, update.temperature(neighbor) - flow);
+            }
+            if flow.abs() > MIN_WAKING_FLOW {
+                update.wake(pos);
+                update.wake(neighbor);
+            }
+        }
+    }
+}
+
+fn update_atom(
+    update: &mut ChunkUpdate,
+    elements: &IdMap<Element>,
+    reactions: &Reactions,
+    rng: TickRng,
+    pos: UVec3,
+) {
+    let id = update[pos].element;
+    // Air never moves by itself, other atoms move through it.
+    if id == Element::AIR_ID {
+        return;
+    }
+    let Some(element) = elements.get(id) else {
+        return;
+    };
+
+    if let Some(into) = element.transitions.at(update.temperature(pos)) {
+        let atom = elements
+            .instance_of(into)
+            .expect("Transitions only reference elements in their own set");
+        update.set(pos, atom);
+        update.mark_moved(pos);
+        return;
+    }
+
+    for (index, reaction) in reactions.of(id).iter().enumerate() {
+        if react(update, elements, rng, pos, index, reaction) {
+            return;
+        }
+    }
+
+    for (index, rule) in element.rules.iter().enumerate() {
+        let roll = rng.chance(pos, Salt::Rule(index));
+        if apply_rule(update, elements, pos, rule, roll) {
+            return;
+        }
+    }
+
+    let moves: &[&[IVec3]] = match element.state {
+        State::Solid => &[],
+        State::Powder => &[&[IVec3::NEG_Y], &DIAGONALS_BELOW],
+        State::Liquid => &[&[IVec3::NEG_Y], &DIAGONALS_BELOW, &HORIZONTAL],
+        State::Gas => &[&[IVec3::Y], &DIAGONALS_ABOVE, &HORIZONTAL],
+    };
+    for &options in moves {
+        if try_move(update, elements, rng, pos, element, options) {
+            return;
+        }
+    }
+}
+
+const FACES: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::Y,
+    IVec3::Z,
+    IVec3::NEG_X,
+    IVec3::NEG_Y,
+    IVec3::NEG_Z,
+];
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::X, IVec3::Z, IVec3::NEG_X, IVec3::NEG_Z];
+
+const DIAGONALS_BELOW: [IVec3; 4] = [
+    IVec3::new(1, -1, 0),
+    IVec3::new(0, -1, 1),
+    IVec3::new(-1, -1, 0),
+    IVec3::new(0, -1, -1),
+];
+
+const DIAGONALS_ABOVE: [IVec3; 4] = [
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 1, 1),
+    IVec3::new(-1, 1, 0),
+    IVec3::new(0, 1, -1),
+];
+
+/// Swaps the atom at `pos` with an atom it can displace at one of the offsets,
+/// if there is any.  Which offset is tried first is random, so atoms don't all
+/// drift the same way.
+fn try_move(
+    update: &mut ChunkUpdate,
+    elements: &IdMap<Element>,
+    rng: TickRng,
+    pos: UVec3,
+    element: &Element,
+    options: &[IVec3],
+) -> bool {
+    let start = rng.below(pos, Salt::Move, options.len());
+    for i in 0..options.len() {
+        let target = pos.as_ivec3() + options[(start + i) % options.len()];
+        if update.contains_atom(target)
+            && elements
+                .get(update[target].element)
+                .is_some_and(|other| can_displace(element, other))
+        {
+            let target = target.as_uvec3();
+            update.swap(pos, target);
+            update.mark_moved(pos);
+            update.mark_moved(target);
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether `element` can swap places with `other` when moving into it.  Gases
+/// rise through heavier atoms, everything else sinks through lighter ones.
+fn can_displace(element: &Element, other: &Element) -> bool {
+    other.state.is_movable()
+        && match element.state {
+            State::Gas => other.density > element.density,
+            _ => other.density < element.density,
+        }
+}
+
+/// Turns the atom at `pos` and a touching atom into the products of
+/// `reaction`, if there is a touching atom of the second reactant and the
+/// reaction's probability roll succeeds.
+fn react(
+    update: &mut ChunkUpdate,
+    elements: &IdMap<Element>,
+    rng: TickRng,
+    pos: UVec3,
+    index: usize,
+    reaction: &Reaction,
+) -> bool {
+    let start = rng.below(pos, Salt::Reaction(index), FACES.len());
+    let neighbor = (0..FACES.len())
+        .map(|i| pos.as_ivec3() + FACES[(start + i) % FACES.len()])
+        .find(|&neighbor| {
+            update.contains_atom(neighbor)
+                && update[neighbor].element == reaction.reactants[1]
+                && !update.has_moved(neighbor.as_uvec3())
+        });
+    let Some(neighbor) = neighbor else {
+        return false;
+    };
+    if rng.chance(pos, Salt::Reaction(index)) >= reaction.probability {
+        // Keep simulating the chunk so the reaction can happen later.
+        update.wake(pos);
+        return false;
+    }
+
+    for (target, (reactant, product)) in [pos, neighbor.as_uvec3()]
+        .into_iter()
+        .zip(reaction.reactants.into_iter().zip(reaction.products))
+    {
+        if reactant != product {
+            let atom = elements
+                .instance_of(product)
+                .expect("Reactions only reference elements in their own set");
+            update.set(target, atom);
+            update.set_temperature(target, elements[product].default_temperature);
+        }
+        update.mark_moved(target);
+    }
+    true
+}
+
+/// Applies `rule` to the atom at `pos` if its pattern matches and `roll` is
+/// below its probability, returning whether it did.
+fn apply_rule(
+    update: &mut ChunkUpdate,
+    elements: &IdMap<Element>,
+    pos: UVec3,
+    rule: &Rule,
+    roll: f32,
+) -> bool {
+    let pos = pos.as_ivec3();
+    let matches = rule.pattern.iter().all(|&(offset, element)| {
+        let neighbor = pos + offset;
+        update.contains_atom(neighbor) && update[neighbor].element == element
+    });
+    let in_world = rule.replace.iter().all(|&(offset, replacement)| {
+        let source_in_world = match replacement {
+            Replacement::Element(_) => true,
+            Replacement::Atom(from) => update.contains_atom(pos + from),
+        };
+        update.contains_atom(pos + offset) && source_in_world
+    });
+    if !matches || !in_world {
+        return false;
+    }
+    if roll >= rule.probability {
+        // Keep simulating the chunk so the rule can apply later.
+        update.wake(pos.as_uvec3());
+        return false;
+    }
+
+    let replacements: Vec<_> = rule
+        .replace
+        .iter()
+        .map(|&(offset, replacement)| {
+            let (atom, temperature) = match replacement {
+                Replacement::Element(id) => (
+                    elements
+                        .instance_of(id)
+                        .expect("Rules only reference elements in their own set"),
+                    elements[id].default_temperature,
+                ),
+                Replacement::Atom(from) => {
+                    (update[pos + from].clone(), update.temperature(pos + from))
+                }
+            };
+            ((pos + offset).as_uvec3(), atom, temperature)
+        })
+        .collect();
+    for (target, atom, temperature) in replacements {
+        update.set(target, atom);
+        update.set_temperature(target, temperature);
+        update.mark_moved(target);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        atom_physics::{
+            id::{IdMap, MappedToId},
+            reaction::Reactions,
+            transition::PhaseTransition,
+        },
+        terrain::{
+            storage::{Atoms, SLEEP_DELAY},
+            Atom,
+        },
+    };
+
+    use super::*;
+
+    fn world_with(state: State) -> (AtomWorld, u8) {
+        let mut elements: IdMap<Element> = Element::create_map();
+        let id = elements
+            .insert(
+                "Test",
+                Element {
+                    state,
+                    density: state.default_density(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let world = AtomWorld {
+            atoms: Atoms::default(),
+            elements,
+            reactions: Reactions::default(),
+            seed: rng::DEFAULT_SEED,
+            tick: 0,
+        };
+        (world, id)
+    }
+
+    #[test]
+    fn falling_atom_moves_one_per_tick() {
+        let (mut world, id) = world_with(State::Powder);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 5, 3), atom);
+
+        step(&mut world);
+        assert_eq!(world.atoms[UVec3::new(3, 4, 3)].element, id);
+        assert_eq!(world.atoms[UVec3::new(3, 5, 3)].element, Element::AIR_ID);
+
+        for _ in 0..10 {
+            step(&mut world);
+        }
+        assert_eq!(world.atoms[UVec3::new(3, 0, 3)].element, id);
+    }
+
+    /// How many of the atoms at `offsets` from `pos` are of element `id`.
+    fn count_at(world: &AtomWorld, pos: UVec3, offsets: &[IVec3], id: u8) -> usize {
+        offsets
+            .iter()
+            .filter(|&&offset| world.atoms[pos.as_ivec3() + offset].element == id)
+            .count()
+    }
+
+    #[test]
+    fn powder_slides_off_pile() {
+        let (mut world, id) = world_with(State::Powder);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 0, 3), atom.clone());
+        world.atoms.set(UVec3::new(3, 1, 3), atom);
+
+        step(&mut world);
+        assert_eq!(world.atoms[UVec3::new(3, 1, 3)].element, Element::AIR_ID);
+        assert_eq!(
+            count_at(&world, UVec3::new(3, 1, 3), &DIAGONALS_BELOW, id),
+            1
+        );
+    }
+
+    #[test]
+    fn liquid_spreads() {
+        let (mut world, id) = world_with(State::Liquid);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 0, 3), atom);
+
+        step(&mut world);
+        assert_eq!(world.atoms[UVec3::new(3, 0, 3)].element, Element::AIR_ID);
+        assert_eq!(count_at(&world, UVec3::new(3, 0, 3), &HORIZONTAL, id), 1);
+    }
+
+    #[test]
+    fn gas_rises() {
+        let (mut world, id) = world_with(State::Gas);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 5, 3), atom);
+
+        step(&mut world);
+        assert_eq!(world.atoms[UVec3::new(3, 6, 3)].element, id);
+    }
+
+    #[test]
+    fn heavier_sinks_through_lighter() {
+        let (mut world, water) = world_with(State::Liquid);
+        let mut add = |name, state, density| {
+            let element = Element {
+                state,
+                density,
+                ..Default::default()
+            };
+            world.elements.insert(name, element).unwrap()
+        };
+        let sand = add("Sand", State::Powder, 1600.0);
+        let oil = add("Oil", State::Liquid, 900.0);
+        let stone = add("Stone", State::Solid, 2700.0);
+        let atom = |id| world.elements.instance_of(id).unwrap();
+
+        // Surrounded by stone so nothing can spread out.
+        let column = UVec3::new(3, 0, 3);
+        for y in 0..4 {
+            for offset in HORIZONTAL {
+                let pos = (column.as_ivec3() + offset).as_uvec3() + UVec3::Y * y;
+                world.atoms.set(pos, atom(stone));
+            }
+        }
+        world.atoms.set(column, atom(oil));
+        world.atoms.set(column + UVec3::Y, atom(water));
+        world.atoms.set(column + UVec3::Y * 2, atom(sand));
+
+        for _ in 0..4 {
+            step(&mut world);
+        }
+        let element_at = |y| world.atoms[column + UVec3::Y * y].element;
+        assert_eq!(
+            [element_at(0), element_at(1), element_at(2)],
+            [sand, water, oil]
+        );
+    }
+
+    #[test]
+    fn rule_swaps_with_neighbor() {
+        let (mut world, id) = world_with(State::Solid);
+        world.elements.get_mut(id).unwrap().rules.push(Rule {
+            pattern: vec![(IVec3::X, Element::AIR_ID)],
+            replace: vec![
+                (IVec3::ZERO, Replacement::Atom(IVec3::X)),
+                (IVec3::X, Replacement::Atom(IVec3::ZERO)),
+            ],
+            probability: 1.0,
+        });
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 5, 3), atom.clone());
+
+        step(&mut world);
+        // Moved exactly once, even though it is updated again at its new
+        // position.
+        assert_eq!(world.atoms[UVec3::new(4, 5, 3)].element, id);
+        assert_eq!(world.atoms[UVec3::new(3, 5, 3)].element, Element::AIR_ID);
+
+        // Stops at the edge of the world.
+        let edge = UVec3::new(world.atoms.size().x - 1, 5, 3);
+        world.atoms.set(edge - UVec3::X, atom);
+        step(&mut world);
+        step(&mut world);
+        assert_eq!(world.atoms[edge].element, id);
+    }
+
+    #[test]
+    fn solid_atom_stays() {
+        let (mut world, id) = world_with(State::Solid);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 5, 3), atom);
+
+        step(&mut world);
+        assert_eq!(world.atoms[UVec3::new(3, 5, 3)].element, id);
+    }
+
+    #[test]
+    fn heat_flows_from_hot_to_cold() {
+        let (mut world, id) = world_with(State::Solid);
+        let atom = world.elements.instance_of(id).unwrap();
+        let hot = UVec3::new(3, 0, 3);
+        let cold = UVec3::new(4, 0, 3);
+        world.atoms.set(hot, atom.clone());
+        world.atoms.set(cold, atom);
+        world.atoms.set_temperature(hot, 100.0);
+        world.atoms.set_temperature(cold, 0.0);
+
+        let total = |world: &AtomWorld| {
+            let size = world.atoms.size();
+            let mut total = 0.0;
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    for x in 0..size.x {
+                        total += world.atoms.temperature(UVec3 { x, y, z }) as f64;
+                    }
+                }
+            }
+            total
+        };
+        let before = total(&world);
+
+        step(&mut world);
+        let (hot_temp, cold_temp) = (world.atoms.temperature(hot), world.atoms.temperature(cold));
+        assert!(hot_temp < 100.0);
+        assert!(cold_temp > 0.0);
+        assert!(hot_temp > cold_temp);
+        assert!((total(&world) - before).abs() < 0.01);
+    }
+
+    #[test]
+    fn hot_atom_melts() {
+        let (mut world, id) = world_with(State::Solid);
+        let liquid_id = world
+            .elements
+            .insert(
+                "Liquid",
+                Element {
+                    state: State::Liquid,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        world.elements.get_mut(id).unwrap().transitions.melts_into = Some(PhaseTransition {
+            into: liquid_id,
+            temperature: 50.0,
+        });
+        let pos = UVec3::new(3, 0, 3);
+        world
+            .atoms
+            .set(pos, world.elements.instance_of(id).unwrap());
+
+        step(&mut world);
+        assert_eq!(world.atoms[pos].element, id);
+
+        world.atoms.set_temperature(pos, 100.0);
+        step(&mut world);
+        assert_eq!(world.atoms[pos].element, liquid_id);
+        assert!(world.atoms.temperature(pos) > 50.0);
+    }
+
+    #[test]
+    fn touching_atoms_react() {
+        let (mut world, fire) = world_with(State::Solid);
+        let wood = world.elements.insert("Wood", Element::default()).unwrap();
+        world.reactions.insert(Reaction {
+            reactants: [fire, wood],
+            products: [fire, fire],
+            probability: 1.0,
+        });
+        let fire_pos = UVec3::new(3, 0, 3);
+        let wood_pos = UVec3::new(3, 1, 3);
+        world
+            .atoms
+            .set(fire_pos, world.elements.instance_of(fire).unwrap());
+        world
+            .atoms
+            .set(wood_pos, world.elements.instance_of(wood).unwrap());
+
+        step(&mut world);
+        assert_eq!(world.atoms[wood_pos].element, fire);
+    }
+
+    /// Fills part of the world with sand and water, then runs it for a while.
+    fn run_mixture(seed: u64, threads: usize) -> AtomWorld {
+        let (mut world, sand) = world_with(State::Powder);
+        let water = world
+            .elements
+            .insert(
+                "Water",
+                Element {
+                    state: State::Liquid,
+                    density: 500.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        world.seed = seed;
+        // Crosses chunk borders on every axis.
+        for x in 8..40 {
+            for z in 8..40 {
+                let pos = UVec3::new(x, (x * z) % 24, z);
+                let id = if (x + z) % 3 == 0 { water } else { sand };
+                world
+                    .atoms
+                    .set(pos, world.elements.instance_of(id).unwrap());
+                world.atoms.set_temperature(pos, (x * 3 + z) as f32);
+            }
+        }
+        for _ in 0..6 {
+            step_with_threads(&mut world, threads);
+        }
+        world
+    }
+
+    fn same_atoms(a: &AtomWorld, b: &AtomWorld) -> bool {
+        (0..32).all(|y| {
+            (0..48).all(|z| {
+                (0..48).all(|x| {
+                    let pos = UVec3 { x, y, z };
+                    a.atoms[pos] == b.atoms[pos]
+                        && a.atoms.temperature(pos).to_bits() == b.atoms.temperature(pos).to_bits()
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        let a = run_mixture(1, 1);
+        assert!(same_atoms(&a, &run_mixture(1, 1)));
+        assert!(!same_atoms(&a, &run_mixture(2, 1)));
+    }
+
+    #[test]
+    fn threads_same_result() {
+        let single = run_mixture(3, 1);
+        assert!(same_atoms(&single, &run_mixture(3, 4)));
+        assert!(same_atoms(&single, &run_mixture(3, 7)));
+    }
+
+    #[test]
+    fn rule_probability() {
+        let (mut world, id) = world_with(State::Solid);
+        world.elements.get_mut(id).unwrap().rules.push(Rule {
+            pattern: Vec::new(),
+            replace: vec![(IVec3::ZERO, Replacement::Element(Element::AIR_ID))],
+            probability: 0.5,
+        });
+        let atom = world.elements.instance_of(id).unwrap();
+        for x in 0..10 {
+            for z in 0..10 {
+                world.atoms.set(UVec3::new(x, 0, z), atom.clone());
+            }
+        }
+
+        step(&mut world);
+        let left = (0..10)
+            .flat_map(|x| (0..10).map(move |z| UVec3::new(x, 0, z)))
+            .filter(|&pos| world.atoms[pos].element == id)
+            .count();
+        assert!((25..75).contains(&left));
+    }
+
+    #[test]
+    fn still_chunks_sleep() {
+        let (mut world, id) = world_with(State::Solid);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(3, 5, 3), atom);
+
+        for _ in 0..SLEEP_DELAY {
+            step(&mut world);
+        }
+        assert!(world.atoms.active_chunks().chunks().is_empty());
+
+        world.atoms.set(UVec3::new(40, 20, 40), Atom::VOID);
+        let active = world.atoms.active_chunks();
+        assert!(active.chunks().contains(&UVec3::new(2, 1, 2)));
+        assert!(!active.chunks().contains(&UVec3::ZERO));
+    }
+
+    #[test]
+    fn falling_atom_crosses_chunks() {
+        let (mut world, id) = world_with(State::Powder);
+        let atom = world.elements.instance_of(id).unwrap();
+        world.atoms.set(UVec3::new(20, 40, 20), atom);
+
+        for _ in 0..40 {
+            step(&mut world);
+        }
+        assert_eq!(world.atoms[UVec3::new(20, 0, 20)].element, id);
+    }
+
+    #[test]
+    fn clock_pause_and_step() {
+        let start = Instant::now();
+        let mut clock = SimulationClock::new(start);
+        assert!(clock.tick(start));
+        assert!(!clock.tick(start));
+
+        clock.set_paused(true, start);
+        assert_eq!(clock.time_until_tick(start), None);
+        let later = start + Duration::from_secs(1);
+        assert!(!clock.tick(later));
+
+        clock.queue_step();
+        assert_eq!(clock.time_until_tick(later), Some(Duration::ZERO));
+        assert!(clock.tick(later));
+        assert!(!clock.tick(later));
+    }
+
+    #[test]
+    fn clock_tick_rate() {
+        let start = Instant::now();
+        let mut clock = SimulationClock::new(start);
+        clock.set_tick_rate(10.0);
+        assert!(clock.tick(start));
+        assert!(!clock.tick(start + Duration::from_millis(50)));
+        assert!(clock.tick(start + Duration::from_millis(150)));
+    }
+}
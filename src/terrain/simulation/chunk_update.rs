@@ -0,0 +1,122 @@
+use std::ops::Index;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::terrain::{
+    storage::{Atoms, GridPos},
+    Atom,
+};
+
+/// The changes made while updating a single chunk, kept separate from the
+/// world so chunks that don't touch each other can be updated at the same
+/// time.
+///
+/// Reads see the changes made so far, so updating a chunk this way gives the
+/// same result as changing the world directly.
+#[derive(Debug)]
+pub struct ChunkUpdate<'a> {
+    atoms: &'a Atoms,
+    /// Positions that were updated in an earlier phase of this tick.
+    moved_before: &'a HashSet<UVec3>,
+    changes: Changes,
+}
+
+/// Changes from a [`ChunkUpdate`] that haven't been applied to the world yet.
+#[derive(Debug, Default)]
+pub struct Changes {
+    atoms: HashMap<UVec3, Atom>,
+    temperatures: HashMap<UVec3, f32>,
+    moved: HashSet<UVec3>,
+    woken: Vec<UVec3>,
+}
+
+impl<'a, T: GridPos> Index<T> for ChunkUpdate<'a> {
+    type Output = Atom;
+
+    fn index(&self, index: T) -> &Self::Output {
+        let pos = index.to_uvec3();
+        self.changes
+            .atoms
+            .get(&pos)
+            .unwrap_or_else(|| &self.atoms[pos])
+    }
+}
+
+impl<'a> ChunkUpdate<'a> {
+    pub fn new(atoms: &'a Atoms, moved_before: &'a HashSet<UVec3>) -> Self {
+        Self {
+            atoms,
+            moved_before,
+            changes: Changes::default(),
+        }
+    }
+
+    pub fn contains_atom(&self, pos: impl GridPos) -> bool {
+        self.atoms.contains_atom(pos)
+    }
+
+    pub fn set(&mut self, pos: UVec3, atom: Atom) {
+        self.changes.atoms.insert(pos, atom);
+    }
+
+    pub fn temperature(&self, pos: impl GridPos) -> f32 {
+        let pos = pos.to_uvec3();
+        self.changes
+            .temperatures
+            .get(&pos)
+            .copied()
+            .unwrap_or_else(|| self.atoms.temperature(pos))
+    }
+
+    pub fn set_temperature(&mut self, pos: UVec3, temperature: f32) {
+        self.changes.temperatures.insert(pos, temperature);
+    }
+
+    /// Swaps the atoms at the two positions, along with their temperatures.
+    pub fn swap(&mut self, a: UVec3, b: UVec3) {
+        let (atom_a, temperature_a) = (self[a].clone(), self.temperature(a));
+        let (atom_b, temperature_b) = (self[b].clone(), self.temperature(b));
+        self.set(a, atom_b);
+        self.set(b, atom_a);
+        self.set_temperature(a, temperature_b);
+        self.set_temperature(b, temperature_a);
+    }
+
+    /// See [`Atoms::wake`].
+    pub fn wake(&mut self, pos: UVec3) {
+        self.changes.woken.push(pos);
+    }
+
+    /// Whether the atom at `pos` was already updated this tick.
+    pub fn has_moved(&self, pos: UVec3) -> bool {
+        self.changes.moved.contains(&pos) || self.moved_before.contains(&pos)
+    }
+
+    /// Marks the atom at `pos` as updated, so it isn't updated again this
+    /// tick.
+    pub fn mark_moved(&mut self, pos: UVec3) {
+        self.changes.moved.insert(pos);
+    }
+
+    pub fn finish(self) -> Changes {
+        self.changes
+    }
+}
+
+impl Changes {
+    pub fn apply(self, atoms: &mut Atoms, moved: &mut HashSet<UVec3>) {
+        for (pos, atom) in self.atoms {
+            atoms.set(pos, atom);
+        }
+        for (pos, temperature) in self.temperatures {
+            atoms.set_temperature(pos, temperature);
+        }
+        for pos in self.woken {
+            atoms.wake(pos);
+        }
+        moved.extend(self.moved);
+    }
+}
@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, DragValue},
+    EguiContexts,
+};
+
+use crate::{
+    atom_physics::{
+        element::{Element, ElementId},
+        id::IdMap,
+    },
+    player::SelectedElement,
+    terrain::{
+        rendering::CHUNK_SIZE,
+        save,
+        storage::{Atoms, MAX_SIZE},
+        thread::{
+            edit::{EditBatch, EditsApplied},
+            TerrainThread,
+        },
+    },
+};
+
+use super::{rng::DEFAULT_SEED, DEFAULT_TICK_RATE};
+
+pub struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, simulation_inspector_system);
+    }
+}
+
+/// The simulation settings last sent to the terrain thread.
+#[derive(Debug, Clone, Copy)]
+struct SimulationSettings {
+    paused: bool,
+    tick_rate: f32,
+    seed: u64,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            tick_rate: DEFAULT_TICK_RATE,
+            seed: DEFAULT_SEED,
+        }
+    }
+}
+
+/// The file the world is saved to and loaded from.
+#[derive(Debug, Clone)]
+struct SavePath(String);
+
+impl Default for SavePath {
+    fn default() -> Self {
+        Self(save::DEFAULT_PATH.to_owned())
+    }
+}
+
+/// The size typed into the world size fields, measured in chunks, which is
+/// reset whenever the world changes size, such as when a save is loaded.
+#[derive(Debug, Clone, Copy, Default)]
+struct WorldSizeFields {
+    /// The world's size when the fields were last reset, measured in atoms.
+    world_size: UVec3,
+    chunks: UVec3,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn simulation_inspector_system(
+    mut contexts: EguiContexts,
+    terrain_thread: Res<TerrainThread>,
+    mut settings: Local<SimulationSettings>,
+    elements: Res<IdMap<Element>>,
+    selected_element: Res<SelectedElement>,
+    mut replaced_element: Local<ElementId>,
+    mut edits_applied: EventReader<EditsApplied>,
+    mut last_edits: Local<EditsApplied>,
+    mut save_path: Local<SavePath>,
+    world: Res<Atoms>,
+    mut size_fields: Local<WorldSizeFields>,
+) {
+    if let Some(applied) = edits_applied.iter().last() {
+        *last_edits = applied.clone();
+    }
+    if world.size() != size_fields.world_size {
+        *size_fields = WorldSizeFields {
+            world_size: world.size(),
+            chunks: world.size() / CHUNK_SIZE as u32,
+        };
+    }
+
+    egui::Window::new("Simulation Inspector")
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let mut paused = settings.paused;
+            ui.checkbox(&mut paused, "Paused");
+            if paused != settings.paused {
+                settings.paused = paused;
+                terrain_thread.set_paused(paused);
+            }
+
+            ui.add_enabled_ui(settings.paused, |ui| {
+                if ui.button("Step").clicked() {
+                    terrain_thread.step();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Tick rate: ");
+                let mut tick_rate = settings.tick_rate;
+                ui.add(
+                    DragValue::new(&mut tick_rate)
+                        .speed(0.5)
+                        .clamp_range(1.0..=240.0)
+                        .suffix(" Hz"),
+                );
+                if tick_rate != settings.tick_rate {
+                    settings.tick_rate = tick_rate;
+                    terrain_thread.set_tick_rate(tick_rate);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Seed: ");
+                let mut seed = settings.seed;
+                ui.add(DragValue::new(&mut seed));
+                if seed != settings.seed {
+                    settings.seed = seed;
+                    terrain_thread.set_seed(seed);
+                }
+            });
+
+            ui.separator();
+
+            let mut batch = EditBatch::default();
+            ui.horizontal(|ui| {
+                let name = |id| elements.get_full(id).map_or("?", |(name, _)| name);
+                egui::ComboBox::new("replaced_element", "")
+                    .selected_text(name(*replaced_element))
+                    .show_ui(ui, |ui| {
+                        for (id, name, _) in elements.iter() {
+                            ui.selectable_value(&mut *replaced_element, id, name);
+                        }
+                    });
+                if ui
+                    .button(format!("Replace with {}", name(selected_element.0)))
+                    .clicked()
+                {
+                    batch.replace_element(*replaced_element, selected_element.0);
+                }
+            });
+            if ui.button("Clear world").clicked() {
+                batch.clear();
+            }
+            terrain_thread.submit_edits(batch);
+
+            ui.label(format!(
+                "Last edits: {} edits, {} chunks changed",
+                last_edits.edits,
+                last_edits.dirty_chunks.len()
+            ));
+
+            ui.separator();
+
+            ui.text_edit_singleline(&mut save_path.0);
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    terrain_thread.save_world(save_path.0.clone().into());
+                }
+                if ui.button("Load").clicked() {
+                    terrain_thread.load_world(save_path.0.clone().into());
+                }
+            });
+
+            ui.separator();
+
+            // Edited in chunks, so the size is always a multiple of the chunk
+            // size.
+            let chunks = &mut size_fields.chunks;
+            ui.horizontal(|ui| {
+                ui.label("World size: ");
+                for axis in chunks.as_mut() {
+                    ui.add(
+                        DragValue::new(axis)
+                            .speed(0.1)
+                            .clamp_range(1..=MAX_SIZE / CHUNK_SIZE as u32)
+                            .custom_formatter(|chunks, _| {
+                                format!("{}", chunks as usize * CHUNK_SIZE)
+                            })
+                            .custom_parser(|text| {
+                                let atoms = text.trim().parse::<f64>().ok()?;
+                                Some((atoms / CHUNK_SIZE as f64).round())
+                            }),
+                    );
+                }
+            });
+            let size = *chunks * CHUNK_SIZE as u32;
+            ui.horizontal(|ui| {
+                if ui.button("New world").clicked() {
+                    terrain_thread.new_world(size);
+                }
+                if ui.button("Resize").clicked() {
+                    terrain_thread.resize_world(size);
+                }
+            });
+        });
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::UVec3;
+
+/// Seed the terrain thread starts with.
+pub const DEFAULT_SEED: u64 = 0x5EED;
+
+/// Random numbers for a single simulation tick.
+///
+/// Each number only depends on the world's seed, the tick, the position it is
+/// for, and a salt, so running a tick from the same state always gives the
+/// same results no matter what order atoms are updated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickRng {
+    key: u64,
+}
+
+/// What a random number is used for, so different uses at the same position
+/// don't get the same number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Salt {
+    Move,
+    Rule(usize),
+    Reaction(usize),
+}
+
+impl Salt {
+    fn to_u64(self) -> u64 {
+        match self {
+            Salt::Move => 0,
+            Salt::Rule(index) => 1 << 32 | index as u64,
+            Salt::Reaction(index) => 2 << 32 | index as u64,
+        }
+    }
+}
+
+impl TickRng {
+    pub fn new(seed: u64, tick: u64) -> Self {
+        Self {
+            key: mix(seed ^ mix(tick)),
+        }
+    }
+
+    pub fn u64(self, pos: UVec3, salt: Salt) -> u64 {
+        let pos = u64::from(pos.x) << 42 | u64::from(pos.y) << 21 | u64::from(pos.z);
+        mix(self.key ^ mix(pos ^ mix(salt.to_u64())))
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn chance(self, pos: UVec3, salt: Salt) -> f32 {
+        (self.u64(pos, salt) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number in `0..len`.
+    pub fn below(self, pos: UVec3, salt: Salt, len: usize) -> usize {
+        (self.u64(pos, salt) % len as u64) as usize
+    }
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chance_is_spread_out() {
+        let rng = TickRng::new(DEFAULT_SEED, 7);
+        let rolls: Vec<_> = (0..1000)
+            .map(|i| rng.chance(UVec3::new(i % 10, i / 10, 0), Salt::Move))
+            .collect();
+        assert!(rolls.iter().all(|roll| (0.0..1.0).contains(roll)));
+        let below_half = rolls.iter().filter(|&&roll| roll < 0.5).count();
+        assert!((400..600).contains(&below_half));
+    }
+
+    #[test]
+    fn depends_on_every_input() {
+        let pos = UVec3::new(1, 2, 3);
+        let value = TickRng::new(1, 2).u64(pos, Salt::Move);
+        assert_eq!(value, TickRng::new(1, 2).u64(pos, Salt::Move));
+        assert_ne!(value, TickRng::new(2, 2).u64(pos, Salt::Move));
+        assert_ne!(value, TickRng::new(1, 3).u64(pos, Salt::Move));
+        assert_ne!(
+            value,
+            TickRng::new(1, 2).u64(UVec3::new(1, 2, 4), Salt::Move)
+        );
+        assert_ne!(value, TickRng::new(1, 2).u64(pos, Salt::Rule(0)));
+        assert_ne!(
+            TickRng::new(1, 2).u64(pos, Salt::Rule(0)),
+            TickRng::new(1, 2).u64(pos, Salt::Reaction(0))
+        );
+    }
+}
@@ -1,78 +1,203 @@
 use std::{
     mem,
     ops::{Deref, Index},
+    sync::OnceLock,
 };
 
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 
 use crate::{
     physics::colliders::{Collides, Rect3d},
     terrain::rendering::CHUNK_SIZE,
 };
 
-use self::array3d::{Array3d, GridPos};
+use self::{array3d::Array3d, palette::PaletteChunk};
+pub use self::{array3d::GridPos, palette::CHUNK_VOLUME};
 
 use super::{change_detection::DetectChanges, rendering::ChunkData, Atom, Direction};
 
 mod array3d;
+mod palette;
 
-type AtomsCurve = array3d::SimpleCurve;
-
-type ChunksCurve = array3d::SimpleCurve;
-type ChunksCurveIterMut<'a> = array3d::SimpleCurveIterMut<'a, ChunkData>;
+/// Layout of each chunk's temperatures, which matches the order of the atoms
+/// in a [`PaletteChunk`].  Build with the `morton_curve` feature to lay them
+/// out along a Morton curve instead; the atoms themselves are unaffected.  The
+/// `benchmark_chunk_access` test times both.
+#[cfg(not(feature = "morton_curve"))]
+type AtomsCurve = array3d::ChunkMajorCurve;
+#[cfg(feature = "morton_curve")]
+type AtomsCurve = array3d::MortonCurve;
 
 pub const DEFAULT_SIZE: UVec3 = UVec3::new(128, 48, 256);
 
-#[derive(Debug, Clone)]
+/// Largest size of each side of the world.  Only the chunks that have been
+/// changed take up memory, so this is far bigger than a world would usually
+/// be filled.
+pub const MAX_SIZE: u32 = 1 << 16;
+
+/// How many ticks a chunk keeps being simulated after something in it changed.
+pub const SLEEP_DELAY: u8 = 8;
+
+/// Temperature of atoms in a new world, in °C.
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+
+/// The atoms in the world, stored as a sparse map of chunks.  Chunks are only
+/// created once something in them changes, and every chunk that isn't stored
+/// is air at [`AMBIENT_TEMPERATURE`].
+///
+/// The world is still a box of [`Self::size`] atoms with a corner at the
+/// origin, so positions are unsigned and anything outside of the box, such as
+/// below the floor, can't be built in.
+#[derive(Debug, Clone, Resource)]
 pub struct Atoms {
-    atoms: Array3d<Atom, AtomsCurve>,
-    chunks: Array3d<ChunkData, ChunksCurve>,
+    /// The stored chunks, by their position measured in chunks.
+    chunks: HashMap<UVec3, StoredChunk>,
+    /// Chunks that were removed since [`Self::take_removed_chunks`] was last
+    /// called, measured in atoms.
+    removed: Vec<UVec3>,
+    size: UVec3,
 }
 
-impl Default for Atoms {
+/// Everything stored about one chunk.
+#[derive(Debug, Clone)]
+struct StoredChunk {
+    atoms: PaletteChunk,
+    temperatures: Array3d<f32, AtomsCurve>,
+    data: ChunkData,
+    /// Ticks left until the chunk stops being simulated.  Sleeping chunks have
+    /// 0.
+    awake_for: u8,
+}
+
+impl Default for StoredChunk {
     fn default() -> Self {
-        // Program might not work properly if world size is not a multiple of
-        // CHUNK_SIZE, so panic here if that happens accidentally.
-        assert_eq!(
-            DEFAULT_SIZE,
-            DEFAULT_SIZE / CHUNK_SIZE as u32 * CHUNK_SIZE as u32
-        );
         Self {
-            atoms: Array3d::new(DEFAULT_SIZE),
-            chunks: Array3d::new(DEFAULT_SIZE / CHUNK_SIZE as u32),
+            atoms: PaletteChunk::default(),
+            temperatures: Array3d::new_filled(UVec3::splat(CHUNK_SIZE as u32), AMBIENT_TEMPERATURE),
+            data: ChunkData::default(),
+            awake_for: 0,
         }
     }
 }
 
+impl StoredChunk {
+    /// What every chunk that isn't stored contains.
+    fn empty() -> &'static Self {
+        static EMPTY: OnceLock<StoredChunk> = OnceLock::new();
+        EMPTY.get_or_init(Self::default)
+    }
+
+    /// Makes the chunk be simulated for at least [`SLEEP_DELAY`] more ticks.
+    fn wake(&mut self) {
+        self.awake_for = SLEEP_DELAY;
+    }
+
+    const fn is_awake(&self) -> bool {
+        self.awake_for > 0
+    }
+
+    /// Counts down towards the chunk sleeping; called once each tick.
+    fn tick_sleep(&mut self) {
+        self.awake_for = self.awake_for.saturating_sub(1);
+    }
+}
+
+impl Default for Atoms {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE)
+    }
+}
+
+/// Whether a world can be `size` atoms big.  Each side must be a non-zero
+/// multiple of [`CHUNK_SIZE`], and no bigger than [`MAX_SIZE`].
+pub fn is_valid_size(size: UVec3) -> bool {
+    size.cmpgt(UVec3::ZERO).all()
+        && size.cmple(UVec3::splat(MAX_SIZE)).all()
+        && size % CHUNK_SIZE as u32 == UVec3::ZERO
+}
+
 impl<T: GridPos> Index<T> for Atoms {
     type Output = Atom;
 
+    /// Positions outside of the world are air, so check with
+    /// [`Atoms::contains_atom`] or use [`Atoms::get`] when that matters.
     fn index(&self, index: T) -> &Self::Output {
         // Negative fields will still be outside of range after bitcast.
-        &self.atoms[index.to_uvec3()]
+        let pos = index.to_uvec3();
+        match self.chunks.get(&(pos / CHUNK_SIZE as u32)) {
+            Some(chunk) => chunk.atoms.get(index_in_chunk(pos)),
+            None => &Atom::AIR,
+        }
     }
 }
 
+/// The index of the atom at `pos` within its [`PaletteChunk`].
+fn index_in_chunk(pos: UVec3) -> usize {
+    let pos = pos % CHUNK_SIZE as u32;
+    PaletteChunk::index_of(pos.x as usize, pos.y as usize, pos.z as usize)
+}
+
 impl Atoms {
+    /// Creates a world full of air.
+    ///
+    /// # Panics
+    ///
+    /// If `size` isn't a valid world size, as the program might not work
+    /// properly otherwise.
+    pub fn new(size: UVec3) -> Self {
+        assert!(is_valid_size(size), "Invalid world size {size}");
+        Self {
+            chunks: HashMap::default(),
+            removed: Vec::new(),
+            size,
+        }
+    }
+
+    /// Copies the world into a world of a different size, cropping it or
+    /// padding it with air on the positive sides.  Every chunk that was kept
+    /// is marked as changed, and the rest are removed.
+    pub fn resized(&self, size: UVec3) -> Self {
+        let mut resized = Self::new(size);
+        let chunk_count = size / CHUNK_SIZE as u32;
+        for (&chunk_pos, chunk) in &self.chunks {
+            if chunk_pos.cmplt(chunk_count).all() {
+                let mut chunk = chunk.clone();
+                chunk.data.mark_changed();
+                chunk.wake();
+                resized.chunks.insert(chunk_pos, chunk);
+            } else {
+                resized.removed.push(chunk_pos * CHUNK_SIZE as u32);
+            }
+        }
+        resized
+    }
+
+    /// Replaces every atom with air and changes the world's size.  Every chunk
+    /// is removed.
+    pub fn reset(&mut self, size: UVec3) {
+        let mut removed = self.take_removed_chunks();
+        removed.extend(self.chunk_positions());
+        *self = Self::new(size);
+        self.removed = removed;
+    }
+
     /// Sets the atom at the specified position.
     pub fn set(&mut self, pos: UVec3, atom: Atom) {
-        let (old_atom, chunk, chunk_pos) = self.atom_mut(pos);
-        if *old_atom != atom {
-            chunk.atom_changed(old_atom, &atom);
-            *old_atom = atom;
-        }
+        let chunk_pos = pos / CHUNK_SIZE as u32;
+        self.replace(pos, atom);
 
         let pos = pos % CHUNK_SIZE as u32;
         macro_rules! update_adjacent {
             ($f:ident, $dir:ident) => {
                 if pos.$f == 0 {
                     if chunk_pos.$f > 0 {
-                        self.chunks[chunk_pos - UVec3::$dir].mark_changed();
+                        self.mark_changed(chunk_pos - UVec3::$dir);
                     }
-                } else if pos.$f == CHUNK_SIZE as u32 - 1
-                    && (chunk_pos.$f + 1) * 16 < self.size().$f
-                {
-                    self.chunks[chunk_pos + UVec3::$dir].mark_changed();
+                } else if pos.$f == CHUNK_SIZE as u32 - 1 {
+                    self.mark_changed(chunk_pos + UVec3::$dir);
                 }
             };
         }
@@ -82,12 +207,159 @@ impl Atoms {
         update_adjacent!(z, Z);
     }
 
-    /// Returns a mutable reference to an atom and the data for the chunk it is
-    /// in.  Note that changing an atom without updating chunk data may result
-    /// in incorrect behavior.
-    fn atom_mut(&mut self, pos: UVec3) -> (&mut Atom, &mut ChunkData, UVec3) {
+    /// Marks the chunk at `chunk_pos`, measured in chunks, as changed if it's
+    /// stored.  Chunks that aren't stored are all air, so don't have a mesh
+    /// that could change.
+    fn mark_changed(&mut self, chunk_pos: UVec3) -> bool {
+        match self.chunks.get_mut(&chunk_pos) {
+            Some(chunk) => {
+                chunk.data.mark_changed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets many atoms at once.  Unlike calling [`Self::set`] for each atom,
+    /// each neighbouring chunk is only marked as changed once, no matter how
+    /// many atoms on its border changed.
+    ///
+    /// Returns the positions of the chunks that need remeshing, measured in
+    /// atoms.
+    pub fn set_many(&mut self, atoms: impl IntoIterator<Item = (UVec3, Atom)>) -> HashSet<UVec3> {
+        // Which faces of each changed chunk had an atom on them change, as a
+        // bitmask indexed by `axis * 2 + is_positive_side`.
+        let mut changed_faces: HashMap<UVec3, u8> = HashMap::default();
+        for (pos, atom) in atoms {
+            if !self.replace(pos, atom) {
+                continue;
+            }
+
+            let chunk_pos = pos / CHUNK_SIZE as u32;
+            let faces = changed_faces.entry(chunk_pos).or_default();
+            let pos = (pos % CHUNK_SIZE as u32).to_array();
+            for (axis, pos) in pos.into_iter().enumerate() {
+                if pos == 0 {
+                    *faces |= 1 << (axis * 2);
+                } else if pos == CHUNK_SIZE as u32 - 1 {
+                    *faces |= 1 << (axis * 2 + 1);
+                }
+            }
+        }
+
+        let mut dirty = HashSet::default();
+        for (chunk_pos, faces) in changed_faces {
+            self.chunks.get_mut(&chunk_pos).unwrap().atoms.compact();
+            dirty.insert(chunk_pos * CHUNK_SIZE as u32);
+            for (axis, dir) in [UVec3::X, UVec3::Y, UVec3::Z].into_iter().enumerate() {
+                if faces & 1 << (axis * 2) != 0
+                    && chunk_pos.to_array()[axis] > 0
+                    && self.mark_changed(chunk_pos - dir)
+                {
+                    dirty.insert((chunk_pos - dir) * CHUNK_SIZE as u32);
+                }
+                if faces & 1 << (axis * 2 + 1) != 0 && self.mark_changed(chunk_pos + dir) {
+                    dirty.insert((chunk_pos + dir) * CHUNK_SIZE as u32);
+                }
+            }
+        }
+        dirty
+    }
+
+    /// The temperature of the atom at the specified position, in °C.
+    pub fn temperature(&self, pos: impl GridPos) -> f32 {
+        let pos = pos.to_uvec3();
+        match self.chunks.get(&(pos / CHUNK_SIZE as u32)) {
+            Some(chunk) => chunk.temperatures[pos % CHUNK_SIZE as u32],
+            None => AMBIENT_TEMPERATURE,
+        }
+    }
+
+    /// Sets the temperature of the atom at the specified position.  Unlike
+    /// [`Self::set`], this never causes the chunk to be remeshed or woken up.
+    pub fn set_temperature(&mut self, pos: UVec3, temperature: f32) {
+        let chunk_pos = pos / CHUNK_SIZE as u32;
+        let chunk = match self.chunks.get_mut(&chunk_pos) {
+            Some(chunk) => chunk,
+            None if temperature == AMBIENT_TEMPERATURE => return,
+            None => self.chunks.entry(chunk_pos).or_default(),
+        };
+        chunk.temperatures[pos % CHUNK_SIZE as u32] = temperature;
+    }
+
+    /// Makes the chunk containing `pos` be simulated for a while, even if
+    /// nothing in it changes.
+    pub fn wake(&mut self, pos: UVec3) {
+        if let Some(chunk) = self.chunks.get_mut(&(pos / CHUNK_SIZE as u32)) {
+            chunk.wake();
+        }
+    }
+
+    pub fn wake_all(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.wake();
+        }
+    }
+
+    /// Whether the chunk containing `pos` is being simulated.
+    pub fn is_awake(&self, pos: UVec3) -> bool {
+        self.chunks
+            .get(&(pos / CHUNK_SIZE as u32))
+            .is_some_and(|chunk| chunk.is_awake())
+    }
+
+    /// Finds the chunks that should be simulated this tick, which are the awake
+    /// chunks and their neighbours, then counts down how long each chunk stays
+    /// awake.
+    pub fn active_chunks(&mut self) -> ActiveChunks {
+        let chunk_count = self.size / CHUNK_SIZE as u32;
+        let mut active = HashSet::new();
+        for (&chunk_pos, chunk) in &mut self.chunks {
+            if chunk.is_awake() {
+                let min = (chunk_pos.as_ivec3() - IVec3::ONE)
+                    .max(IVec3::ZERO)
+                    .as_uvec3();
+                let max = (chunk_pos + UVec3::ONE).min(chunk_count - UVec3::ONE);
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        for z in min.z..=max.z {
+                            active.insert(UVec3 { x, y, z });
+                        }
+                    }
+                }
+            }
+            chunk.tick_sleep();
+        }
+
+        let mut chunks: Vec<_> = active.into_iter().collect();
+        chunks.sort_unstable_by_key(|pos| (pos.y, pos.z, pos.x));
+        ActiveChunks { chunks }
+    }
+
+    /// Replaces the atom at `pos` and updates the data for its chunk, but not
+    /// neighbouring chunks.  Returns whether the atom changed.
+    fn replace(&mut self, pos: UVec3, atom: Atom) -> bool {
         let chunk_pos = pos / CHUNK_SIZE as u32;
-        (&mut self.atoms[pos], &mut self.chunks[chunk_pos], chunk_pos)
+        let index = index_in_chunk(pos);
+        let chunk = match self.chunks.get_mut(&chunk_pos) {
+            Some(chunk) => chunk,
+            None if atom == Atom::AIR => return false,
+            None => self.chunks.entry(chunk_pos).or_default(),
+        };
+        let old_atom = chunk.atoms.get(index);
+        if *old_atom == atom {
+            return false;
+        }
+        chunk.data.atom_changed(old_atom, &atom);
+        chunk.wake();
+        chunk.atoms.set(index, atom);
+        true
+    }
+
+    /// The atom at `pos`, or `None` if `pos` is outside of the world.
+    pub fn get(&self, pos: impl GridPos) -> Option<&Atom> {
+        let pos = pos.to_uvec3();
+        self.contains_atom(pos).then(|| &self[pos])
     }
 
     pub fn contains_point(&self, point: Vec3) -> bool {
@@ -100,38 +372,144 @@ impl Atoms {
         pos.x < self.size().x && pos.y < self.size().y && pos.z < self.size().z
     }
 
-    pub fn chunks(&mut self) -> Chunks {
-        Chunks {
-            chunk_iter: self.chunks.iter_mut_labeled(),
-            atoms: &self.atoms,
+    /// Positions of the stored chunks, measured in atoms.
+    pub fn chunk_positions(&self) -> impl Iterator<Item = UVec3> + '_ {
+        self.chunks.keys().map(|&pos| pos * CHUNK_SIZE as u32)
+    }
+
+    /// Positions of the chunks that changed since this was last called,
+    /// measured in atoms, and marks them as unchanged.
+    pub fn take_changed_chunks(&mut self) -> Vec<UVec3> {
+        self.chunks
+            .iter_mut()
+            .filter_map(|(&pos, chunk)| {
+                chunk.data.take_changed().then_some(pos * CHUNK_SIZE as u32)
+            })
+            .collect()
+    }
+
+    /// Positions of the chunks that were removed since this was last called,
+    /// measured in atoms.  Their meshes and copies need to be removed too.
+    pub fn take_removed_chunks(&mut self) -> Vec<UVec3> {
+        mem::take(&mut self.removed)
+    }
+
+    /// The chunk at `pos`, measured in atoms, and its data.
+    pub fn chunk(&self, pos: UVec3) -> (Chunk<'_>, &ChunkData) {
+        let stored = self
+            .chunks
+            .get(&(pos / CHUNK_SIZE as u32))
+            .unwrap_or_else(|| StoredChunk::empty());
+        let chunk = Chunk {
+            pos,
+            index: 0,
+            palette: &stored.atoms,
+            atoms: self,
+        };
+        (chunk, &stored.data)
+    }
+
+    /// The temperatures in the chunk at `pos`, measured in atoms, in the same
+    /// order as [`Self::chunk`].
+    pub fn chunk_temperatures(&self, pos: UVec3) -> impl Iterator<Item = f32> + '_ {
+        let temperatures = &self
+            .chunks
+            .get(&(pos / CHUNK_SIZE as u32))
+            .unwrap_or_else(|| StoredChunk::empty())
+            .temperatures;
+        self.chunk(pos)
+            .0
+            .map(move |atom| temperatures[atom.pos() % CHUNK_SIZE as u32])
+    }
+
+    /// Copies the atoms in the chunk at `pos`, measured in atoms.
+    pub fn snapshot(&self, pos: UVec3) -> ChunkSnapshot {
+        let (chunk, _) = self.chunk(pos);
+        ChunkSnapshot {
+            pos,
+            atoms: chunk.map(|atom| (*atom).clone()).collect(),
+        }
+    }
+
+    /// Replaces the atoms in a chunk with ones from [`Self::snapshot`].
+    pub fn load_snapshot(&mut self, snapshot: ChunkSnapshot) {
+        self.insert_chunk(snapshot.pos, snapshot.atoms, None);
+    }
+
+    /// Replaces the chunk at `pos`, measured in atoms, with [`CHUNK_VOLUME`]
+    /// atoms and optionally their temperatures, ordered the same way as
+    /// [`Self::chunk`].  The chunk is marked as changed, along with its
+    /// neighbours since their meshes depend on it.
+    ///
+    /// [`CHUNK_VOLUME`]: palette::CHUNK_VOLUME
+    pub fn insert_chunk(&mut self, pos: UVec3, atoms: Vec<Atom>, temperatures: Option<Vec<f32>>) {
+        let chunk_pos = pos / CHUNK_SIZE as u32;
+        let mut chunk = StoredChunk::default();
+        for atom in &atoms {
+            chunk.data.__add_atom(atom);
+        }
+        chunk.data.mark_changed();
+        chunk.atoms = PaletteChunk::from_atoms(atoms);
+        if let Some(temperatures) = temperatures {
+            for (temperature, local) in chunk.temperatures.iter_mut_labeled() {
+                *temperature = temperatures[index_in_chunk(local)];
+            }
+        }
+        self.chunks.insert(chunk_pos, chunk);
+        self.mark_neighbours_changed(chunk_pos);
+    }
+
+    /// Removes the chunk at `pos`, measured in atoms, leaving air in its place.
+    /// Returns whether there was a chunk there.
+    pub fn remove_chunk(&mut self, pos: UVec3) -> bool {
+        let chunk_pos = pos / CHUNK_SIZE as u32;
+        if self.chunks.remove(&chunk_pos).is_none() {
+            return false;
+        }
+        self.removed.push(pos);
+        self.mark_neighbours_changed(chunk_pos);
+        true
+    }
+
+    fn mark_neighbours_changed(&mut self, chunk_pos: UVec3) {
+        for direction in Direction::DIRECTIONS {
+            let neighbour = chunk_pos.as_ivec3() + direction.normal_ivec();
+            if neighbour.cmpge(IVec3::ZERO).all() {
+                self.mark_changed(neighbour.as_uvec3());
+            }
         }
     }
 
     /// Grants mutable access to every atom sequentially, in a way that makes
-    /// modification faster than using [`Self::set`] on each atom.
+    /// modification faster than using [`Self::set`] on each atom.  Only atoms
+    /// in stored chunks are visited, since the rest are air.
     pub fn modify_all(&mut self, mut f: impl FnMut(DetectChanges<Atom>)) {
-        for (chunk_data, chunk_pos) in self.chunks.iter_mut_labeled() {
-            let offset = chunk_pos * CHUNK_SIZE as u32;
+        for chunk in self.chunks.values_mut() {
+            let chunk_data = &mut chunk.data;
+            let atoms = &mut chunk.atoms;
             chunk_data.__reset_counts();
-            for x in 0..CHUNK_SIZE as u32 {
-                for y in 0..CHUNK_SIZE as u32 {
-                    for z in 0..CHUNK_SIZE as u32 {
-                        let atom = &mut self.atoms[offset + UVec3 { x, y, z }];
-                        let mut changed = false;
-                        f(DetectChanges::new(atom, &mut changed));
-
-                        if changed {
-                            chunk_data.mark_changed();
-                        }
-                        chunk_data.__add_atom(atom);
-                    }
+            let mut any_changed = false;
+            for index in 0..palette::CHUNK_VOLUME {
+                let mut atom = atoms.get(index).clone();
+                let mut changed = false;
+                f(DetectChanges::new(&mut atom, &mut changed));
+
+                chunk_data.__add_atom(&atom);
+                if changed {
+                    chunk_data.mark_changed();
+                    any_changed = true;
+                    atoms.set(index, atom);
                 }
             }
+            atoms.compact();
+            if any_changed {
+                chunk.wake();
+            }
         }
     }
 
     pub const fn size(&self) -> UVec3 {
-        self.atoms.size()
+        self.size
     }
 
     pub fn raycast(
@@ -337,81 +715,108 @@ impl Atoms {
     }
 }
 
-pub struct Chunks<'a> {
-    chunk_iter: ChunksCurveIterMut<'a>,
-    atoms: &'a Array3d<Atom, AtomsCurve>,
+/// The chunks that are simulated in a tick.
+#[derive(Debug, Clone)]
+pub struct ActiveChunks {
+    chunks: Vec<UVec3>,
 }
 
-impl<'a> Iterator for Chunks<'a> {
-    type Item = (UVec3, Chunk<'a>, &'a mut ChunkData);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.chunk_iter.next().map(|(chunk_data, pos)| {
-            let pos = pos * CHUNK_SIZE as u32;
-            (
-                pos,
-                Chunk {
-                    pos,
-                    offset: UVec3::new(u32::MAX, 0, 0),
-                    atoms: self.atoms,
-                },
-                chunk_data,
-            )
-        })
+impl ActiveChunks {
+    /// Positions of the active chunks, measured in chunks, from the bottom of
+    /// the world to the top.
+    pub fn chunks(&self) -> &[UVec3] {
+        &self.chunks
     }
 }
 
+/// A copy of the atoms in one chunk, which is how changes made on the terrain
+/// thread reach the ECS's copy of the world.
+#[derive(Debug, Clone)]
+pub struct ChunkSnapshot {
+    pos: UVec3,
+    /// Ordered the same way as [`Chunk`].
+    atoms: Vec<Atom>,
+}
+
+/// Iterates over the atoms in a chunk, in the order they're stored in.
 #[derive(Debug, Clone)]
 pub struct Chunk<'a> {
     pos: UVec3,
-    offset: UVec3,
-    atoms: &'a Array3d<Atom, AtomsCurve>,
+    /// Index of the next atom within the chunk.
+    index: usize,
+    palette: &'a PaletteChunk,
+    atoms: &'a Atoms,
 }
 
 impl<'a> Iterator for Chunk<'a> {
     type Item = AtomRef<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.offset.x = self.offset.x.wrapping_add(1);
-        let try_x = self.offset.x % CHUNK_SIZE as u32;
-        if self.offset.x != try_x {
-            self.offset.x = try_x;
-            self.offset.z += 1;
-            let try_z = self.offset.z % CHUNK_SIZE as u32;
-            if self.offset.z != try_z {
-                self.offset.z = try_z;
-                self.offset.y += 1;
-            }
+        if self.index == palette::CHUNK_VOLUME {
+            return None;
         }
-        (self.offset.y < CHUNK_SIZE as u32).then_some(AtomRef {
-            pos: self.pos + self.offset,
+        let index = self.index;
+        self.index += 1;
+        let size = CHUNK_SIZE as u32;
+        let local = UVec3 {
+            x: index as u32 % size,
+            y: index as u32 / (size * size),
+            z: index as u32 / size % size,
+        };
+        Some(AtomRef {
+            local,
+            chunk_pos: self.pos,
+            palette: self.palette,
             atoms: self.atoms,
         })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = palette::CHUNK_VOLUME - self.index;
+        (remaining, Some(remaining))
+    }
 }
 
+/// An atom and a way to look at its neighbours.  Neighbours in the same chunk
+/// are found without looking up the chunk again.
 #[derive(Debug, Clone, Copy)]
 pub struct AtomRef<'a> {
-    pos: UVec3,
-    atoms: &'a Array3d<Atom, AtomsCurve>,
+    /// Position within the chunk.
+    local: UVec3,
+    chunk_pos: UVec3,
+    palette: &'a PaletteChunk,
+    atoms: &'a Atoms,
 }
 
 impl<'a> Deref for AtomRef<'a> {
     type Target = Atom;
 
     fn deref(&self) -> &Self::Target {
-        &self.atoms[self.pos]
+        self.palette.get(PaletteChunk::index_of(
+            self.local.x as usize,
+            self.local.y as usize,
+            self.local.z as usize,
+        ))
     }
 }
 
 impl<'a> AtomRef<'a> {
     pub fn pos(&self) -> UVec3 {
-        self.pos
+        self.chunk_pos + self.local
     }
 
     pub fn in_direction(&self, direction: Direction) -> &Atom {
+        let local = self.local.as_ivec3() + direction.normal_ivec();
+        if local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(CHUNK_SIZE as i32)).all() {
+            return self.palette.get(PaletteChunk::index_of(
+                local.x as usize,
+                local.y as usize,
+                local.z as usize,
+            ));
+        }
         self.atoms
-            .get_or(self.pos.as_ivec3() + direction.normal_ivec(), &Atom::VOID)
+            .get(self.chunk_pos.as_ivec3() + local)
+            .unwrap_or(&Atom::VOID)
     }
 }
 
@@ -425,6 +830,8 @@ pub struct RaycastHit {
 
 #[cfg(test)]
 mod tests {
+    use std::{any, time::Instant};
+
     use crate::terrain::{color::AtomColor, JoinFace};
 
     use super::*;
@@ -432,14 +839,34 @@ mod tests {
     #[test]
     fn chunk_iter_correct_length() {
         let mut count = 0;
-        let mut world = Atoms::default();
-        let (_, chunk, _) = world.chunks().nth(3).unwrap();
+        let world = Atoms::default();
+        let (chunk, _) = world.chunk(UVec3::new(48, 0, 0));
         for _ in chunk {
             count += 1;
         }
         assert_eq!(count, CHUNK_SIZE.pow(3));
     }
 
+    #[test]
+    fn neighbours_across_chunks() {
+        let mut world = Atoms::default();
+        let atom = Atom {
+            color: AtomColor::from_u32(0xff0000ff),
+            join_face: JoinFace::SameAlpha,
+            element: 2,
+        };
+        world.set(UVec3::new(16, 0, 5), atom.clone());
+        world.set(UVec3::new(14, 0, 5), atom.clone());
+        let (mut chunk, _) = world.chunk(UVec3::ZERO);
+        let edge = chunk
+            .find(|atom| atom.pos() == UVec3::new(15, 0, 5))
+            .unwrap();
+        assert_eq!(*edge.in_direction(Direction::PosX), atom);
+        assert_eq!(*edge.in_direction(Direction::NegX), atom);
+        assert_eq!(*edge.in_direction(Direction::PosZ), Atom::AIR);
+        assert_eq!(*edge.in_direction(Direction::NegY), Atom::VOID);
+    }
+
     #[test]
     fn all_atoms_placeable() {
         let mut world = Atoms::default();
@@ -458,4 +885,137 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut world = Atoms::default();
+        let atom = Atom {
+            color: AtomColor::from_u32(0xff0000ff),
+            join_face: JoinFace::SameAlpha,
+            element: 2,
+        };
+        world.set(UVec3::new(17, 3, 5), atom.clone());
+        assert_eq!(world.take_changed_chunks(), [UVec3::new(16, 0, 0)]);
+        assert!(world.take_changed_chunks().is_empty());
+
+        let mut copy = Atoms::default();
+        copy.load_snapshot(world.snapshot(UVec3::new(16, 0, 0)));
+        assert_eq!(copy[UVec3::new(17, 3, 5)], atom);
+        assert_eq!(copy[UVec3::new(17, 3, 6)], Atom::AIR);
+        assert_eq!(copy.take_changed_chunks(), [UVec3::new(16, 0, 0)]);
+    }
+
+    #[test]
+    fn set_many_marks_neighbours_once() {
+        let mut world = Atoms::default();
+        let atom = Atom {
+            color: AtomColor::from_u32(0xff0000ff),
+            join_face: JoinFace::SameAlpha,
+            element: 2,
+        };
+        // Only chunks that are stored need remeshing, so store the neighbour.
+        world.set(UVec3::new(0, 0, 0), atom.clone());
+        world.take_changed_chunks();
+        let dirty = world
+            .set_many((1..CHUNK_SIZE as u32 - 1).map(|z| (UVec3::new(16, 5, z), atom.clone())));
+        let expected = [UVec3::new(0, 0, 0), UVec3::new(16, 0, 0)];
+        assert_eq!(dirty, HashSet::from_iter(expected));
+        let mut changed = world.take_changed_chunks();
+        changed.sort_by_key(|pos| pos.x);
+        assert_eq!(changed, expected);
+
+        // Setting atoms to what they already are changes nothing.
+        assert!(world
+            .set_many([(UVec3::new(16, 5, 1), atom.clone())])
+            .is_empty());
+    }
+
+    #[test]
+    fn resize_keeps_overlap() {
+        let mut world = Atoms::new(UVec3::new(32, 16, 32));
+        let atom = Atom {
+            color: AtomColor::from_u32(0xff0000ff),
+            join_face: JoinFace::SameAlpha,
+            element: 2,
+        };
+        world.set(UVec3::new(3, 4, 5), atom.clone());
+        world.set(UVec3::new(20, 4, 5), atom.clone());
+        world.set_temperature(UVec3::new(3, 4, 5), 50.0);
+        world.take_changed_chunks();
+
+        let mut resized = world.resized(UVec3::new(16, 32, 48));
+        assert_eq!(resized.size(), UVec3::new(16, 32, 48));
+        assert_eq!(resized[UVec3::new(3, 4, 5)], atom);
+        assert_eq!(resized.temperature(UVec3::new(3, 4, 5)), 50.0);
+        assert_eq!(resized[UVec3::new(15, 20, 40)], Atom::AIR);
+        assert_eq!(resized.take_changed_chunks(), [UVec3::new(0, 0, 0)]);
+        assert_eq!(resized.take_removed_chunks(), [UVec3::new(16, 0, 0)]);
+    }
+
+    /// Reads every atom's neighbours through [`AtomRef::in_direction`], like
+    /// meshing does, then every atom's neighbours' temperatures, like heat
+    /// conduction does.  Only the temperatures are laid out by [`AtomsCurve`],
+    /// so compare runs with and without the `morton_curve` feature.
+    ///
+    /// Run with `cargo test --release benchmark_chunk_access -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_chunk_access() {
+        let mut world = Atoms::default();
+        let atom = Atom {
+            color: AtomColor::from_u32(0xff0000ff),
+            join_face: JoinFace::SameAlpha,
+            element: 2,
+        };
+        // A checkerboard, so every chunk is stored and none is uniform.
+        let size = world.size();
+        let mut atoms = Vec::new();
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let pos = UVec3 { x, y, z };
+                    if (x + y + z) % 2 == 0 {
+                        atoms.push((pos, atom.clone()));
+                    }
+                    world.set_temperature(pos, (x + y + z) as f32);
+                }
+            }
+        }
+        world.set_many(atoms);
+
+        for _ in 0..3 {
+            let start = Instant::now();
+            let mut faces = 0;
+            for pos in world.chunk_positions() {
+                let (chunk, _) = world.chunk(pos);
+                for atom in chunk {
+                    for direction in Direction::DIRECTIONS {
+                        faces += usize::from(atom.in_direction(direction).color.a != atom.color.a);
+                    }
+                }
+            }
+            let neighbours = start.elapsed();
+
+            let start = Instant::now();
+            let mut flow = 0.0;
+            for chunk_pos in world.chunk_positions() {
+                for y in 0..CHUNK_SIZE as u32 {
+                    for z in 0..CHUNK_SIZE as u32 {
+                        for x in 0..CHUNK_SIZE as u32 {
+                            let pos = chunk_pos + UVec3 { x, y, z };
+                            let temperature = world.temperature(pos);
+                            for offset in [UVec3::X, UVec3::Y, UVec3::Z] {
+                                flow += world.temperature(pos + offset) - temperature;
+                            }
+                        }
+                    }
+                }
+            }
+            let temperatures = start.elapsed();
+            println!(
+                "{}: neighbours {neighbours:?} ({faces}), temperatures {temperatures:?} ({flow})",
+                any::type_name::<AtomsCurve>()
+            );
+        }
+    }
 }
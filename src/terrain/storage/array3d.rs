@@ -1,10 +1,17 @@
-use std::{
-    iter,
-    ops::{Index, IndexMut},
-};
+use std::ops::{Index, IndexMut};
 
 use bevy::prelude::*;
 
+#[cfg(not(feature = "morton_curve"))]
+pub use self::chunk_major::ChunkMajorCurve;
+#[cfg(feature = "morton_curve")]
+pub use self::morton::MortonCurve;
+
+#[cfg(any(test, not(feature = "morton_curve")))]
+mod chunk_major;
+#[cfg(any(test, feature = "morton_curve"))]
+mod morton;
+
 /// A 3D array with options to specify how data is laid out in memory.
 #[derive(Debug, Clone)]
 pub struct Array3d<T, C> {
@@ -13,43 +20,21 @@ pub struct Array3d<T, C> {
     curve: C,
 }
 
-impl<T> Array3d<T, SimpleCurve> {
-    /// Uses the default mapping.
-    pub fn new(size: UVec3) -> Array3d<T, SimpleCurve>
-    where
-        T: Default,
-    {
-        Array3d::new_with_curve(size, SimpleCurve)
+impl<T: Clone, C: SpaceFillingCurve + Default> Array3d<T, C> {
+    /// Uses the default mapping, with every element set to `value`.
+    pub fn new_filled(size: UVec3, value: T) -> Self {
+        Self::new_filled_with_curve(size, value, C::default())
     }
 }
 
-impl<T, C: SpaceFillingCurve> Array3d<T, C> {
-    pub fn new_with_curve(size: UVec3, curve: C) -> Self
-    where
-        T: Default,
-    {
+impl<T: Clone, C: SpaceFillingCurve> Array3d<T, C> {
+    pub fn new_filled_with_curve(size: UVec3, value: T, curve: C) -> Self {
         let area = curve.data_length(size);
-
-        let mut data = Vec::with_capacity(area);
-        data.extend(iter::repeat_with(T::default).take(area));
-        let data = data.into_boxed_slice();
-
-        Self { data, size, curve }
-    }
-
-    pub const fn size(&self) -> UVec3 {
-        self.size
-    }
-
-    /// Returns a mutable reference to the element at the index, or `None` if
-    /// the index is not within the bounds of this.
-    pub fn get(&self, index: UVec3) -> Option<&T> {
-        (index.x < self.size.x && index.y < self.size.y && index.z < self.size.z)
-            .then(|| &self[index])
-    }
-
-    pub fn get_or<'a>(&'a self, index: impl GridPos, default: &'a T) -> &'a T {
-        self.get(index.to_uvec3()).unwrap_or(default)
+        Self {
+            data: vec![value; area].into_boxed_slice(),
+            size,
+            curve,
+        }
     }
 }
 
@@ -98,7 +83,7 @@ pub trait IterableCurve: SpaceFillingCurve + Sized {
 /// Simpleist mapping from 3D to 1D space.
 ///
 /// Very fast, but poor cache efficiency.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct SimpleCurve;
 
 impl SpaceFillingCurve for SimpleCurve {
@@ -165,8 +150,9 @@ impl GridPos for IVec3 {
 mod tests {
     use super::*;
 
-    fn test_curve(size: UVec3, curve: impl SpaceFillingCurve) {
-        let mut world: Array3d<Option<UVec3>, _> = Array3d::new_with_curve(size, curve);
+    pub(super) fn test_curve(size: UVec3, curve: impl IterableCurve) {
+        let mut world: Array3d<Option<UVec3>, _> =
+            Array3d::new_filled_with_curve(size, None, curve);
         for x in 0..size.x {
             for y in 0..size.y {
                 for z in 0..size.z {
@@ -183,6 +169,13 @@ mod tests {
                 }
             }
         }
+        for (expected, pos) in world.iter_mut_labeled() {
+            assert_eq!(
+                *expected,
+                Some(pos),
+                "Iterator labels are wrong (size: {size})"
+            );
+        }
     }
 
     #[test]
@@ -0,0 +1,101 @@
+use std::iter;
+
+use bevy::prelude::*;
+
+use crate::terrain::{rendering::CHUNK_SIZE, storage::palette::CHUNK_VOLUME};
+
+use super::{IterableCurve, SimpleCurve, SpaceFillingCurve};
+
+/// Mapping that stores each chunk contiguously, so everything in a chunk can
+/// be read without jumping around in memory.
+///
+/// Chunks are laid out like [`SimpleCurve`], and points within a chunk are in
+/// the same order as in a [`PaletteChunk`](crate::terrain::storage::palette::PaletteChunk).
+/// Every side of the array must be a multiple of [`CHUNK_SIZE`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkMajorCurve;
+
+impl SpaceFillingCurve for ChunkMajorCurve {
+    fn data_length(&self, size: UVec3) -> usize {
+        assert_eq!(
+            size % CHUNK_SIZE as u32,
+            UVec3::ZERO,
+            "Size must be a multiple of the chunk size"
+        );
+        size.x as usize * size.y as usize * size.z as usize
+    }
+
+    fn index_of(&self, size: UVec3, pos: UVec3) -> usize {
+        let chunk = SimpleCurve.index_of(size / CHUNK_SIZE as u32, pos / CHUNK_SIZE as u32);
+        let local = SimpleCurve.index_of(UVec3::splat(CHUNK_SIZE as u32), pos % CHUNK_SIZE as u32);
+        chunk * CHUNK_VOLUME + local
+    }
+}
+
+impl IterableCurve for ChunkMajorCurve {
+    type IterMut<'a, T> = ChunkMajorCurveIterMut<'a, T> where T: 'a;
+
+    fn iter_mut_labeled<'a, T>(&'a self, data: &'a mut [T], size: UVec3) -> Self::IterMut<'a, T> {
+        ChunkMajorCurveIterMut {
+            iter: data.iter_mut().enumerate(),
+            chunks: size / CHUNK_SIZE as u32,
+        }
+    }
+}
+
+pub struct ChunkMajorCurveIterMut<'a, T> {
+    iter: iter::Enumerate<std::slice::IterMut<'a, T>>,
+    /// How many chunks there are along each axis.
+    chunks: UVec3,
+}
+
+impl<'a, T> Iterator for ChunkMajorCurveIterMut<'a, T> {
+    type Item = (&'a mut T, UVec3);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(index, item)| {
+            let size = CHUNK_SIZE as u32;
+            let chunk = (index / CHUNK_VOLUME) as u32;
+            let local = (index % CHUNK_VOLUME) as u32;
+            let chunk = UVec3 {
+                x: chunk % self.chunks.x,
+                y: chunk / (self.chunks.x * self.chunks.z),
+                z: chunk / self.chunks.x % self.chunks.z,
+            };
+            let local = UVec3 {
+                x: local % size,
+                y: local / (size * size),
+                z: local / size % size,
+            };
+            (item, chunk * size + local)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::storage::palette::PaletteChunk;
+
+    use super::{super::tests::test_curve, *};
+
+    #[test]
+    fn chunk_major_curve() {
+        test_curve(UVec3::new(16, 16, 16), ChunkMajorCurve);
+        test_curve(UVec3::new(256, 16, 16), ChunkMajorCurve);
+        test_curve(UVec3::new(16, 256, 16), ChunkMajorCurve);
+        test_curve(UVec3::new(16, 16, 256), ChunkMajorCurve);
+        test_curve(UVec3::new(48, 32, 80), ChunkMajorCurve);
+    }
+
+    #[test]
+    fn matches_palette_order() {
+        let size = UVec3::new(32, 32, 32);
+        let pos = UVec3::new(16 + 3, 16 + 4, 5);
+        let index = ChunkMajorCurve.index_of(size, pos);
+        assert_eq!(index % CHUNK_VOLUME, PaletteChunk::index_of(3, 4, 5));
+    }
+}
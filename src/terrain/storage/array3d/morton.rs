@@ -0,0 +1,122 @@
+use std::iter;
+
+use bevy::prelude::*;
+
+use super::{IterableCurve, SimpleCurve, SpaceFillingCurve};
+
+/// Mapping along a Morton (Z-order) curve, so points that are close in 3D space
+/// tend to be close in memory too.
+///
+/// The array is split into the largest cubes with a power of two side that
+/// tile it exactly.  Points within a cube follow the Morton curve, and the
+/// cubes themselves are laid out like [`SimpleCurve`], so no space is wasted
+/// on sizes that aren't powers of two.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MortonCurve;
+
+impl MortonCurve {
+    /// Largest cube side supported, as a power of two, so that the Morton part
+    /// of an index fits in a `u32`.
+    const MAX_CUBE_BITS: u32 = 10;
+
+    /// The side of the cubes that tile an array of `size`, as a power of two.
+    fn cube_bits(size: UVec3) -> u32 {
+        size.x
+            .trailing_zeros()
+            .min(size.y.trailing_zeros())
+            .min(size.z.trailing_zeros())
+            .min(Self::MAX_CUBE_BITS)
+    }
+
+    /// Puts two zero bits between each of the lowest 10 bits of `n`.
+    fn spread(n: u32) -> u32 {
+        let n = n & 0x3ff;
+        let n = (n | n << 16) & 0x030000ff;
+        let n = (n | n << 8) & 0x0300f00f;
+        let n = (n | n << 4) & 0x030c30c3;
+        (n | n << 2) & 0x09249249
+    }
+
+    /// The inverse of [`Self::spread`].
+    fn compact(n: u32) -> u32 {
+        let n = n & 0x09249249;
+        let n = (n | n >> 2) & 0x030c30c3;
+        let n = (n | n >> 4) & 0x0300f00f;
+        let n = (n | n >> 8) & 0x030000ff;
+        (n | n >> 16) & 0x3ff
+    }
+}
+
+impl SpaceFillingCurve for MortonCurve {
+    fn index_of(&self, size: UVec3, pos: UVec3) -> usize {
+        let bits = Self::cube_bits(size);
+        let local = pos & UVec3::splat((1 << bits) - 1);
+        let morton =
+            Self::spread(local.x) | Self::spread(local.z) << 1 | Self::spread(local.y) << 2;
+        let cube_index = SimpleCurve.index_of(size >> bits, pos >> bits);
+        cube_index << (3 * bits) | morton as usize
+    }
+}
+
+impl IterableCurve for MortonCurve {
+    type IterMut<'a, T> = MortonCurveIterMut<'a, T> where T: 'a;
+
+    fn iter_mut_labeled<'a, T>(&'a self, data: &'a mut [T], size: UVec3) -> Self::IterMut<'a, T> {
+        let bits = Self::cube_bits(size);
+        MortonCurveIterMut {
+            iter: data.iter_mut().enumerate(),
+            bits,
+            cubes: size >> bits,
+        }
+    }
+}
+
+pub struct MortonCurveIterMut<'a, T> {
+    iter: iter::Enumerate<std::slice::IterMut<'a, T>>,
+    bits: u32,
+    /// How many cubes there are along each axis.
+    cubes: UVec3,
+}
+
+impl<'a, T> Iterator for MortonCurveIterMut<'a, T> {
+    type Item = (&'a mut T, UVec3);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(index, item)| {
+            let morton = (index & ((1 << (3 * self.bits)) - 1)) as u32;
+            let local = UVec3 {
+                x: MortonCurve::compact(morton),
+                y: MortonCurve::compact(morton >> 2),
+                z: MortonCurve::compact(morton >> 1),
+            };
+            let cube_index = (index >> (3 * self.bits)) as u32;
+            let cube = UVec3 {
+                x: cube_index % self.cubes.x,
+                y: cube_index / (self.cubes.x * self.cubes.z),
+                z: cube_index / self.cubes.x % self.cubes.z,
+            };
+            (item, cube << self.bits | local)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::test_curve, *};
+
+    #[test]
+    fn morton_curve() {
+        test_curve(UVec3::new(8, 8, 8), MortonCurve);
+        test_curve(UVec3::new(256, 16, 8), MortonCurve);
+        test_curve(UVec3::new(16, 256, 8), MortonCurve);
+        test_curve(UVec3::new(8, 16, 256), MortonCurve);
+        test_curve(UVec3::new(48, 32, 80), MortonCurve);
+        test_curve(UVec3::new(7, 3, 5), MortonCurve);
+        test_curve(UVec3::new(2048, 2, 2048), MortonCurve);
+        test_curve(UVec3::new(128, 48, 256), MortonCurve);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::terrain::{rendering::CHUNK_SIZE, Atom};
+
+/// Number of atoms in a chunk.
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// The atoms in one chunk, stored as a palette of the distinct atoms in the
+/// chunk and an index into the palette for each atom.
+///
+/// Indices are packed into as few bits as the palette needs, so a chunk with
+/// only one kind of atom, like all air, stores no indices at all.
+#[derive(Debug, Clone)]
+pub struct PaletteChunk {
+    /// May contain atoms that are no longer used, until the palette is
+    /// compacted.
+    palette: Vec<Atom>,
+    indices: PackedIndices,
+}
+
+impl Default for PaletteChunk {
+    fn default() -> Self {
+        Self::filled(Atom::AIR)
+    }
+}
+
+impl PaletteChunk {
+    pub fn filled(atom: Atom) -> Self {
+        Self {
+            palette: vec![atom],
+            indices: PackedIndices::new(0),
+        }
+    }
+
+    /// Creates a chunk from [`CHUNK_VOLUME`] atoms, ordered by their index.
+    pub fn from_atoms(atoms: impl IntoIterator<Item = Atom>) -> Self {
+        let mut chunk = Self::default();
+        let mut len = 0;
+        for (index, atom) in atoms.into_iter().enumerate() {
+            chunk.set(index, atom);
+            len += 1;
+        }
+        assert_eq!(len, CHUNK_VOLUME, "Wrong number of atoms for a chunk");
+        chunk.compact();
+        chunk
+    }
+
+    /// The index of the atom at a position relative to the chunk.  Atoms are
+    /// ordered by y, then z, then x.
+    pub const fn index_of(x: usize, y: usize, z: usize) -> usize {
+        x + z * CHUNK_SIZE + y * CHUNK_SIZE * CHUNK_SIZE
+    }
+
+    pub fn get(&self, index: usize) -> &Atom {
+        &self.palette[self.indices.get(index)]
+    }
+
+    pub fn set(&mut self, index: usize, atom: Atom) {
+        let palette_index = match self.palette.iter().position(|a| *a == atom) {
+            Some(palette_index) => palette_index,
+            None => {
+                if self.palette.len() == self.indices.capacity() {
+                    self.compact();
+                }
+                if self.palette.len() == self.indices.capacity() {
+                    let bits = (self.indices.bits * 2).max(1);
+                    self.indices = self.indices.repacked(bits, |i| i);
+                }
+                self.palette.push(atom);
+                self.palette.len() - 1
+            }
+        };
+        self.indices.set(index, palette_index);
+    }
+
+    /// Removes atoms from the palette that are no longer used, and shrinks the
+    /// indices to match.
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..CHUNK_VOLUME {
+            used[self.indices.get(index)] = true;
+        }
+        if used.iter().all(|&used| used)
+            && PackedIndices::bits_for(self.palette.len()) == self.indices.bits
+        {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (old, atom) in self.palette.drain(..).enumerate() {
+            if used[old] {
+                remap[old] = palette.len();
+                palette.push(atom);
+            }
+        }
+        self.palette = palette;
+        self.indices = self
+            .indices
+            .repacked(PackedIndices::bits_for(self.palette.len()), |i| remap[i]);
+    }
+}
+
+/// [`CHUNK_VOLUME`] indices packed into `bits` bits each.
+#[derive(Debug, Clone)]
+struct PackedIndices {
+    /// Always 0 or a power of two, so no index is split between two words.
+    bits: u32,
+    words: Box<[u64]>,
+}
+
+impl PackedIndices {
+    fn new(bits: u32) -> Self {
+        let words = CHUNK_VOLUME * bits as usize / u64::BITS as usize;
+        Self {
+            bits,
+            words: vec![0; words].into_boxed_slice(),
+        }
+    }
+
+    /// The fewest bits needed to store indices into a palette of `len` atoms.
+    fn bits_for(len: usize) -> u32 {
+        match len {
+            0 | 1 => 0,
+            _ => (usize::BITS - (len - 1).leading_zeros()).next_power_of_two(),
+        }
+    }
+
+    /// How many distinct indices can be stored.
+    fn capacity(&self) -> usize {
+        1 << self.bits
+    }
+
+    fn get(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let bit = index * self.bits as usize;
+        let word = self.words[bit / u64::BITS as usize];
+        let mask = u64::MAX >> (u64::BITS - self.bits);
+        (word >> (bit % u64::BITS as usize) & mask) as usize
+    }
+
+    fn set(&mut self, index: usize, value: usize) {
+        if self.bits == 0 {
+            debug_assert_eq!(value, 0);
+            return;
+        }
+        let bit = index * self.bits as usize;
+        let word = &mut self.words[bit / u64::BITS as usize];
+        let shift = bit % u64::BITS as usize;
+        let mask = u64::MAX >> (u64::BITS - self.bits) << shift;
+        *word = *word & !mask | (value as u64) << shift & mask;
+    }
+
+    /// Copies the indices into ones with a different number of bits, changing
+    /// each index with `f`.
+    fn repacked(&self, bits: u32, mut f: impl FnMut(usize) -> usize) -> Self {
+        let mut new = Self::new(bits);
+        for index in 0..CHUNK_VOLUME {
+            new.set(index, f(self.get(index)));
+        }
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::color::AtomColor;
+
+    use super::*;
+
+    fn atom(grey: u8) -> Atom {
+        Atom {
+            color: AtomColor::from_grey(grey),
+            ..Atom::AIR
+        }
+    }
+
+    #[test]
+    fn stores_every_atom() {
+        let mut chunk = PaletteChunk::default();
+        for index in 0..CHUNK_VOLUME {
+            chunk.set(index, atom((index % 251) as u8));
+        }
+        for index in 0..CHUNK_VOLUME {
+            assert_eq!(*chunk.get(index), atom((index % 251) as u8));
+        }
+        chunk.compact();
+        assert_eq!(chunk.palette.len(), 251);
+    }
+
+    #[test]
+    fn compacts_unused_atoms() {
+        let mut chunk = PaletteChunk::default();
+        for index in 0..10 {
+            chunk.set(index, atom(index as u8));
+        }
+        for index in 0..10 {
+            chunk.set(index, Atom::AIR);
+        }
+        chunk.compact();
+        assert_eq!(chunk.palette.len(), 1);
+        assert_eq!(chunk.indices.bits, 0);
+        assert_eq!(*chunk.get(5), Atom::AIR);
+    }
+}
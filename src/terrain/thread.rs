@@ -1,72 +1,333 @@
-use std::thread;
+use std::{
+    any::Any,
+    ops::ControlFlow,
+    path::PathBuf,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
 
-use bevy::prelude::{error, Commands, Plugin, Resource, Startup};
-use crossbeam_channel::{RecvError, SendError};
+use bevy::{
+    app::AppExit,
+    prelude::{
+        error, resource_exists, Commands, Event, EventReader, EventWriter, Events,
+        IntoSystemConfigs, Last, Local, Plugin, PreUpdate, Res, ResMut, Resource, Startup, UVec3,
+        Update, World,
+    },
+};
+use bevy_egui::{egui, EguiContexts};
+use crossbeam_channel::{RecvError, RecvTimeoutError, SendError};
 
-use crate::atom_physics::{self, element::Element};
+use crate::atom_physics::{self, element::Element, id::MappedToId, reaction::Reactions};
 
-use super::{storage::Atoms, AtomWorld};
+use super::{
+    rendering::mesh_gen::{self, MeshUpdate},
+    save,
+    simulation::{self, rng, SimulationClock},
+    storage::{self, Atoms, ChunkSnapshot},
+    AtomWorld, InitialWorldSize,
+};
+
+use self::{
+    edit::{Edit, EditBatch, EditsApplied},
+    streaming::ChunkStore,
+};
+
+pub mod edit;
+mod streaming;
 
 pub(super) struct ThreadPlugin;
 
 impl Plugin for ThreadPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(Startup, spawn_terrain_thread_system);
+        app.add_systems(Startup, spawn_terrain_thread_system)
+            .add_event::<EditsApplied>()
+            .add_event::<TerrainThreadPanicked>()
+            .add_systems(
+                PreUpdate,
+                (
+                    watch_terrain_thread_system,
+                    (receive_snapshots_system, receive_edit_acks_system)
+                        .run_if(resource_exists::<TerrainThreadHandle>()),
+                )
+                    .chain(),
+            )
+            .add_systems(Update, terrain_thread_error_system)
+            .add_systems(Last, shutdown_terrain_thread_system);
     }
 }
 
-fn spawn_terrain_thread_system(mut commands: Commands) {
+fn spawn_terrain_thread_system(mut commands: Commands, size: Res<InitialWorldSize>) {
+    let (thread, handle) = spawn_terrain_thread(size.0);
+    commands.insert_resource(thread);
+    commands.insert_resource(TerrainThreadHandle(handle));
+}
+
+fn spawn_terrain_thread(size: UVec3) -> (TerrainThread, JoinHandle<()>) {
     let (outside_sender, reciever) = crossbeam_channel::unbounded();
-    let (outside_reciever, sender) = crossbeam_channel::unbounded();
+    let (sender, outside_reciever) = crossbeam_channel::unbounded();
+    let (snapshot_sender, snapshot_reciever) = crossbeam_channel::unbounded();
+    let (ack_sender, ack_reciever) = crossbeam_channel::unbounded();
 
-    thread::Builder::new()
+    let handle = thread::Builder::new()
         .name("Terrain Thread".to_owned())
-        .spawn(|| {
-            let mut channel = Channel { sender, reciever };
+        .spawn(move || {
+            let channel = Channel {
+                sender,
+                snapshot_sender,
+                ack_sender,
+                reciever,
+            };
 
             let mut world = AtomWorld {
-                atoms: Atoms::default(),
+                atoms: Atoms::new(size),
                 elements: Element::create_map(),
+                reactions: Reactions::default(),
+                seed: rng::DEFAULT_SEED,
+                tick: 0,
             };
+            let mut clock = SimulationClock::new(Instant::now());
+            let mut store = ChunkStore::in_temp_dir();
 
             loop {
-                match main_loop(&mut world, &mut channel) {
-                    Ok(()) => continue,
-                    Err(e) => match e {
-                        CommunicationError::Send => error!("Main event loop dropped it's reciever"),
-                        CommunicationError::Recv => error!("Main event loop dropped it's sender"),
-                    },
+                match main_loop(&mut world, &mut clock, &mut store, &channel) {
+                    Ok(ControlFlow::Continue(())) => continue,
+                    Ok(ControlFlow::Break(())) => break,
+                    Err(e) => {
+                        match e {
+                            CommunicationError::Send => {
+                                error!("Main event loop dropped it's reciever")
+                            }
+                            CommunicationError::Recv => {
+                                error!("Main event loop dropped it's sender")
+                            }
+                        }
+                        break;
+                    }
                 }
             }
         })
         .expect("Unable to create terrain thread!");
 
-    commands.insert_resource(TerrainThread {
+    let thread = TerrainThread {
         sender: outside_sender,
         reciever: outside_reciever,
-    });
+        snapshot_reciever,
+        ack_reciever,
+    };
+    (thread, handle)
+}
+
+/// The terrain thread, while it is running.  Removed once the thread stops.
+#[derive(Debug, Resource)]
+struct TerrainThreadHandle(JoinHandle<()>);
+
+/// Sent when the terrain thread panics, after which the world stops updating.
+#[derive(Debug, Clone, Event)]
+pub struct TerrainThreadPanicked {
+    pub message: String,
+}
+
+/// Checks whether the terrain thread stopped unexpectedly.
+fn watch_terrain_thread_system(world: &mut World) {
+    let finished = world
+        .get_resource::<TerrainThreadHandle>()
+        .is_some_and(|handle| handle.0.is_finished());
+    if finished {
+        if let Some(message) = join_terrain_thread(world) {
+            world.send_event(TerrainThreadPanicked { message });
+        }
+    }
+}
+
+/// Waits for the terrain thread to stop, returning the panic message if it
+/// panicked.
+fn join_terrain_thread(world: &mut World) -> Option<String> {
+    let TerrainThreadHandle(handle) = world.remove_resource()?;
+    handle.join().err().map(panic_message)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "Unknown error".to_owned(),
+        },
+    }
+}
+
+fn terrain_thread_error_system(
+    mut contexts: EguiContexts,
+    mut events: EventReader<TerrainThreadPanicked>,
+    mut error: Local<Option<String>>,
+) {
+    if let Some(event) = events.iter().last() {
+        *error = Some(event.message.clone());
+    }
+    if let Some(message) = &*error {
+        egui::Window::new("Terrain Thread Error")
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                ui.label("The terrain thread crashed, so the world will no longer update.");
+                ui.label(message);
+            });
+    }
+}
+
+/// Stops the terrain thread and waits for it to finish when the app exits.
+fn shutdown_terrain_thread_system(world: &mut World) {
+    if world.resource::<Events<AppExit>>().is_empty() {
+        return;
+    }
+    if let Some(thread) = world.get_resource::<TerrainThread>() {
+        thread.shutdown();
+    }
+    if let Some(message) = join_terrain_thread(world) {
+        error!("Terrain thread panicked: {message}");
+    }
+}
+
+/// Copies the chunks that changed on the terrain thread into the ECS's copy
+/// of the world, then asks for the next changes.
+fn receive_snapshots_system(thread: Res<TerrainThread>, mut atoms: ResMut<Atoms>) {
+    for update in thread.snapshot_reciever.try_iter() {
+        match update {
+            AtomsUpdate::Chunk(snapshot) => atoms.load_snapshot(snapshot),
+            AtomsUpdate::Resized(size) => *atoms = Atoms::new(size),
+            AtomsUpdate::Removed(pos) => {
+                atoms.remove_chunk(pos);
+            }
+        }
+    }
+    // Only the terrain thread needs to know which chunks were removed.
+    atoms.take_removed_chunks();
+    thread.request_changes();
+}
+
+fn receive_edit_acks_system(thread: Res<TerrainThread>, mut events: EventWriter<EditsApplied>) {
+    events.send_batch(thread.ack_reciever.try_iter());
 }
 
 #[derive(Debug, Clone, Resource)]
 pub struct TerrainThread {
     sender: crossbeam_channel::Sender<Message>,
     reciever: crossbeam_channel::Receiver<MeshUpdate>,
+    snapshot_reciever: crossbeam_channel::Receiver<AtomsUpdate>,
+    ack_reciever: crossbeam_channel::Receiver<EditsApplied>,
 }
 
+/// A change to the ECS's copy of the world.
 #[derive(Debug)]
-enum Message {
-    LoadSet(atom_physics::io::SetHandle),
-    UpdateMeshes,
+enum AtomsUpdate {
+    Chunk(ChunkSnapshot),
+    /// The world changed size.  Snapshots of every chunk with atoms in it
+    /// follow.
+    Resized(UVec3),
+    /// The chunk at this position was removed or unloaded, so it's all air.
+    Removed(UVec3),
 }
 
 #[derive(Debug)]
-struct MeshUpdate {}
+enum Message {
+    LoadSet(atom_physics::io::SetHandle),
+    /// Send meshes and snapshots of the chunks that changed.
+    SendChanges,
+    /// Edits that are queued and applied together before the next tick.
+    Edit(Vec<Edit>),
+    SetPaused(bool),
+    SetTickRate(f32),
+    SetSeed(u64),
+    Step,
+    SaveWorld(PathBuf),
+    LoadWorld(PathBuf),
+    /// Replace the world with an empty one of the given size.
+    NewWorld(UVec3),
+    /// Crop or pad the world to the given size.
+    ResizeWorld(UVec3),
+    /// Where the player is, so chunks around them stay loaded.
+    SetFocus(UVec3),
+    /// Stop the thread.
+    Shutdown,
+}
 
 impl TerrainThread {
     pub fn load_set(&self, set: atom_physics::io::SetHandle) {
         Self::handle_communication_error(self.sender.send(Message::LoadSet(set)));
     }
 
+    /// Pauses or resumes the simulation.
+    pub fn set_paused(&self, paused: bool) {
+        Self::handle_communication_error(self.sender.send(Message::SetPaused(paused)));
+    }
+
+    /// Sets how many simulation ticks should run per second.
+    pub fn set_tick_rate(&self, tick_rate: f32) {
+        Self::handle_communication_error(self.sender.send(Message::SetTickRate(tick_rate)));
+    }
+
+    /// Sets the seed for the simulation's random numbers.
+    pub fn set_seed(&self, seed: u64) {
+        Self::handle_communication_error(self.sender.send(Message::SetSeed(seed)));
+    }
+
+    /// Runs a single simulation tick, even if the simulation is paused.
+    pub fn step(&self) {
+        Self::handle_communication_error(self.sender.send(Message::Step));
+    }
+
+    /// Saves the world to a file.
+    pub fn save_world(&self, path: PathBuf) {
+        Self::handle_communication_error(self.sender.send(Message::SaveWorld(path)));
+    }
+
+    /// Replaces the world with one saved with [`Self::save_world`].
+    pub fn load_world(&self, path: PathBuf) {
+        Self::handle_communication_error(self.sender.send(Message::LoadWorld(path)));
+    }
+
+    /// Replaces the world with an empty one.  Each side of `size` must be a
+    /// multiple of [`CHUNK_SIZE`](crate::terrain::rendering::CHUNK_SIZE).
+    pub fn new_world(&self, size: UVec3) {
+        Self::handle_communication_error(self.sender.send(Message::NewWorld(size)));
+    }
+
+    /// Crops or pads the world to a new size, keeping the atoms at the same
+    /// positions.  Each side of `size` must be a multiple of
+    /// [`CHUNK_SIZE`](crate::terrain::rendering::CHUNK_SIZE).
+    pub fn resize_world(&self, size: UVec3) {
+        Self::handle_communication_error(self.sender.send(Message::ResizeWorld(size)));
+    }
+
+    /// Sets where the player is, measured in atoms.  Chunks near this are
+    /// kept in memory and chunks far from it are unloaded to disk.
+    pub fn set_focus(&self, pos: UVec3) {
+        Self::handle_communication_error(self.sender.send(Message::SetFocus(pos)));
+    }
+
+    /// Stops the thread.  Messages sent after this are ignored.
+    pub fn shutdown(&self) {
+        Self::handle_communication_error(self.sender.send(Message::Shutdown));
+    }
+
+    /// Sends edits to be applied before the next tick.  Once they have been
+    /// applied, an [`EditsApplied`] event is sent.
+    pub fn submit_edits(&self, batch: EditBatch) {
+        if !batch.is_empty() {
+            Self::handle_communication_error(self.sender.send(Message::Edit(batch.edits)));
+        }
+    }
+
+    /// Asks for meshes and snapshots of the chunks that changed since they
+    /// were last sent.
+    fn request_changes(&self) {
+        Self::handle_communication_error(self.sender.send(Message::SendChanges));
+    }
+
+    /// The meshes that finished generating since this was last called.
+    pub fn mesh_updates(&self) -> impl Iterator<Item = MeshUpdate> + '_ {
+        self.reciever.try_iter()
+    }
+
     fn handle_communication_error<T: Into<CommunicationError>>(res: Result<(), T>) {
         match res {
             Ok(()) => {}
@@ -80,6 +341,8 @@ impl TerrainThread {
 
 struct Channel {
     sender: crossbeam_channel::Sender<MeshUpdate>,
+    snapshot_sender: crossbeam_channel::Sender<AtomsUpdate>,
+    ack_sender: crossbeam_channel::Sender<EditsApplied>,
     reciever: crossbeam_channel::Receiver<Message>,
 }
 
@@ -89,33 +352,152 @@ enum CommunicationError {
 }
 
 impl<T> From<SendError<T>> for CommunicationError {
-    fn from(value: SendError<T>) -> Self {
+    fn from(_: SendError<T>) -> Self {
         CommunicationError::Send
     }
 }
 
 impl From<RecvError> for CommunicationError {
-    fn from(value: RecvError) -> Self {
+    fn from(_: RecvError) -> Self {
         CommunicationError::Recv
     }
 }
 
-fn main_loop(world: &mut AtomWorld, channel: Channel) -> Result<(), CommunicationError> {
-    let mut update_meshes = false;
+fn main_loop(
+    world: &mut AtomWorld,
+    clock: &mut SimulationClock,
+    store: &mut ChunkStore,
+    channel: &Channel,
+) -> Result<ControlFlow<()>, CommunicationError> {
+    let mut send_changes = false;
+    let mut edits = Vec::new();
+    let old_size = world.atoms.size();
+
+    // Sleep until either a message arrives or the next tick is due.
+    let first_message = match clock.time_until_tick(Instant::now()) {
+        Some(timeout) => match channel.reciever.recv_timeout(timeout) {
+            Ok(message) => Some(message),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return Err(CommunicationError::Recv),
+        },
+        None => Some(channel.reciever.recv()?),
+    };
+    for message in first_message.into_iter().chain(channel.reciever.try_iter()) {
+        let flow = process_message(message, &mut send_changes, &mut edits, world, clock, store);
+        if flow.is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+
+    if world.atoms.size() != old_size {
+        channel
+            .snapshot_sender
+            .send(AtomsUpdate::Resized(world.atoms.size()))?;
+    }
 
-    let first_message = channel.reciever.recv()?;
-    process_message(first_message, &mut update_meshes, world);
+    if !edits.is_empty() {
+        let applied = if store.prepare_edits(world, &edits) {
+            edit::apply_edits(world, edits)
+        } else {
+            // Editing a chunk that's still on disk would be undone once it's
+            // loaded.
+            error!("Unable to load the chunks edited; skipping the edits");
+            EditsApplied::default()
+        };
+        channel.ack_sender.send(applied)?;
+    }
 
-    for message in channel.reciever.try_iter() {
-        process_message(message, &mut update_meshes, world);
+    store.update(world);
+    let now = Instant::now();
+    while clock.tick(now) {
+        simulation::step(world);
+        store.update(world);
     }
 
-    Ok(())
+    if send_changes {
+        for pos in world.atoms.take_removed_chunks() {
+            channel.sender.send(MeshUpdate::empty(pos))?;
+            channel.snapshot_sender.send(AtomsUpdate::Removed(pos))?;
+        }
+        for pos in world.atoms.take_changed_chunks() {
+            channel
+                .sender
+                .send(mesh_gen::generate_chunk_meshes(&world.atoms, pos))?;
+            channel
+                .snapshot_sender
+                .send(AtomsUpdate::Chunk(world.atoms.snapshot(pos)))?;
+        }
+    }
+
+    Ok(ControlFlow::Continue(()))
 }
 
-fn process_message(message: Message, update_meshes: &mut bool, world: &mut AtomWorld) {
+fn process_message(
+    message: Message,
+    send_changes: &mut bool,
+    edits: &mut Vec<Edit>,
+    world: &mut AtomWorld,
+    clock: &mut SimulationClock,
+    store: &mut ChunkStore,
+) -> ControlFlow<()> {
     match message {
         Message::LoadSet(set) => atom_physics::io::load_and_reload_set(set, world),
-        Message::UpdateMeshes => *update_meshes = true,
+        Message::SendChanges => *send_changes = true,
+        Message::Edit(new_edits) => edits.extend(new_edits),
+        Message::SetPaused(paused) => clock.set_paused(paused, Instant::now()),
+        Message::SetTickRate(tick_rate) => clock.set_tick_rate(tick_rate),
+        Message::SetSeed(seed) => world.seed = seed,
+        Message::Step => clock.queue_step(),
+        Message::SaveWorld(path) => {
+            if store.load_all(world) {
+                save::save_to_file(world, &path);
+            } else {
+                error!(
+                    "Unable to load every chunk; not saving to {}",
+                    path.display()
+                );
+            }
+        }
+        Message::LoadWorld(path) => {
+            if save::load_from_file(world, &path) {
+                store.clear();
+            }
+        }
+        Message::NewWorld(size) if storage::is_valid_size(size) => {
+            store.clear();
+            world.atoms.reset(size);
+        }
+        Message::ResizeWorld(size) if storage::is_valid_size(size) => {
+            if store.load_all(world) {
+                world.atoms = world.atoms.resized(size);
+            } else {
+                error!("Unable to load every chunk; not resizing the world");
+            }
+        }
+        Message::NewWorld(size) | Message::ResizeWorld(size) => {
+            error!("Invalid world size {size}");
+        }
+        Message::SetFocus(pos) => store.set_focus(pos),
+        Message::Shutdown => return ControlFlow::Break(()),
+    }
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_on_shutdown() {
+        let (thread, handle) = spawn_terrain_thread(UVec3::splat(16));
+        thread.shutdown();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn stops_when_disconnected() {
+        let (thread, handle) = spawn_terrain_thread(UVec3::splat(16));
+        drop(thread);
+        handle.join().unwrap();
     }
 }
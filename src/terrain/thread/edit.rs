@@ -0,0 +1,287 @@
+//! Edits to the terrain thread's world, sent in batches.
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    atom_physics::element::{Element, ElementId},
+    terrain::{rendering::CHUNK_SIZE, storage::CHUNK_VOLUME, Atom, AtomWorld, Direction},
+};
+
+/// A single change to the world.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Places an atom of `element`, at that element's default temperature.
+    SetAtom { pos: UVec3, element: ElementId },
+    /// Fills every position from `min` to `max` inclusive with atoms of
+    /// `element`.
+    FillBox {
+        min: UVec3,
+        max: UVec3,
+        element: ElementId,
+    },
+    /// Replaces every atom of `from` with an atom of `to`.
+    ReplaceElement { from: ElementId, to: ElementId },
+    /// Replaces every atom with air.
+    Clear,
+}
+
+/// Edits that are sent to the terrain thread together and applied between
+/// two ticks.
+#[derive(Debug, Clone, Default)]
+pub struct EditBatch {
+    pub(super) edits: Vec<Edit>,
+}
+
+impl EditBatch {
+    pub fn push(&mut self, edit: Edit) {
+        self.edits.push(edit);
+    }
+
+    pub fn set_atom(&mut self, pos: UVec3, element: ElementId) {
+        self.push(Edit::SetAtom { pos, element });
+    }
+
+    pub fn fill_box(&mut self, min: UVec3, max: UVec3, element: ElementId) {
+        self.push(Edit::FillBox { min, max, element });
+    }
+
+    pub fn replace_element(&mut self, from: ElementId, to: ElementId) {
+        self.push(Edit::ReplaceElement { from, to });
+    }
+
+    pub fn clear(&mut self) {
+        self.push(Edit::Clear);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+/// Sent once the terrain thread has applied the edits it was sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Event)]
+pub struct EditsApplied {
+    /// How many edits were applied.
+    pub edits: usize,
+    /// The chunks that changed, measured in atoms.
+    pub dirty_chunks: HashSet<UVec3>,
+}
+
+/// Applies edits in order.  Positions outside of the world and unknown
+/// elements are ignored.
+pub fn apply_edits(world: &mut AtomWorld, edits: impl IntoIterator<Item = Edit>) -> EditsApplied {
+    let mut applied = EditsApplied::default();
+    for edit in edits {
+        applied.edits += 1;
+        let atoms = match edit {
+            Edit::SetAtom { pos, element } => vec![(pos, element)],
+            Edit::FillBox { min, max, element } => {
+                // Sizes are never zero, so this can't underflow.
+                let max = max.min(world.atoms.size() - UVec3::ONE);
+                let mut atoms = Vec::new();
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        for z in min.z..=max.z {
+                            atoms.push((UVec3 { x, y, z }, element));
+                        }
+                    }
+                }
+                atoms
+            }
+            Edit::ReplaceElement { from, to } => {
+                let atoms = positions_where(world, |atom| atom.element == from)
+                    .map(|pos| (pos, to))
+                    .collect();
+                if from == Element::AIR_ID {
+                    // Chunks that aren't stored are all air, so they're
+                    // filled whole instead of being searched.
+                    applied.dirty_chunks.extend(fill_unstored_chunks(world, to));
+                }
+                atoms
+            }
+            Edit::Clear => positions_where(world, |atom| atom.element != Element::AIR_ID)
+                .map(|pos| (pos, Element::AIR_ID))
+                .collect(),
+        };
+        applied.dirty_chunks.extend(set_atoms(world, atoms));
+    }
+    applied
+}
+
+/// Sets atoms along with their temperatures, returning the chunks that need
+/// remeshing.
+fn set_atoms(world: &mut AtomWorld, atoms: Vec<(UVec3, ElementId)>) -> HashSet<UVec3> {
+    let elements = &world.elements;
+    let atoms_world = &mut world.atoms;
+    let mut instances = Vec::with_capacity(atoms.len());
+    for (pos, element) in atoms {
+        if !atoms_world.contains_atom(pos) {
+            continue;
+        }
+        if let Some(atom) = elements.instance_of(element) {
+            atoms_world.set_temperature(pos, elements[element].default_temperature);
+            instances.push((pos, atom));
+        }
+    }
+    atoms_world.set_many(instances)
+}
+
+/// Fills every chunk that isn't stored with atoms of `element`, returning the
+/// chunks that need remeshing.
+fn fill_unstored_chunks(world: &mut AtomWorld, element: ElementId) -> HashSet<UVec3> {
+    let mut dirty = HashSet::default();
+    let Some(atom) = world.elements.instance_of(element) else {
+        return dirty;
+    };
+    if element == Element::AIR_ID {
+        return dirty;
+    }
+    let temperature = world.elements[element].default_temperature;
+    let stored: HashSet<_> = world.atoms.chunk_positions().collect();
+    let chunk_count = world.atoms.size() / CHUNK_SIZE as u32;
+    for x in 0..chunk_count.x {
+        for y in 0..chunk_count.y {
+            for z in 0..chunk_count.z {
+                let chunk_pos = UVec3 { x, y, z };
+                let pos = chunk_pos * CHUNK_SIZE as u32;
+                if stored.contains(&pos) {
+                    continue;
+                }
+                world.atoms.insert_chunk(
+                    pos,
+                    vec![atom.clone(); CHUNK_VOLUME],
+                    Some(vec![temperature; CHUNK_VOLUME]),
+                );
+                world.atoms.wake(pos);
+                dirty.insert(pos);
+                // The faces of the neighbours that touch the chunk changed too.
+                for direction in Direction::DIRECTIONS {
+                    let neighbour = chunk_pos.as_ivec3() + direction.normal_ivec();
+                    if neighbour.cmpge(IVec3::ZERO).all()
+                        && neighbour.as_uvec3().cmplt(chunk_count).all()
+                    {
+                        dirty.insert(neighbour.as_uvec3() * CHUNK_SIZE as u32);
+                    }
+                }
+            }
+        }
+    }
+    dirty
+}
+
+/// Positions of the atoms that `f` returns true for.  Only stored chunks are
+/// searched, so air in chunks that aren't stored isn't found.
+fn positions_where<'a>(
+    world: &'a AtomWorld,
+    mut f: impl FnMut(&Atom) -> bool + 'a,
+) -> impl Iterator<Item = UVec3> + 'a {
+    world.atoms.chunk_positions().flat_map(move |pos| {
+        let (chunk, _) = world.atoms.chunk(pos);
+        chunk
+            .filter(|atom| f(atom))
+            .map(|atom| atom.pos())
+            .collect::<Vec<_>>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        atom_physics::{id::MappedToId, reaction::Reactions},
+        terrain::{
+            color::AtomColor,
+            storage::{Atoms, DEFAULT_SIZE},
+        },
+    };
+
+    use super::*;
+
+    /// A world with stone and sand elements.
+    fn world() -> (AtomWorld, ElementId, ElementId) {
+        let mut world = AtomWorld {
+            atoms: Atoms::default(),
+            elements: Element::create_map(),
+            reactions: Reactions::default(),
+            seed: 0,
+            tick: 0,
+        };
+        let mut solid = |name: &str, grey, default_temperature| {
+            let element = Element {
+                color: AtomColor::from_grey(grey),
+                default_temperature,
+                ..Default::default()
+            };
+            world.elements.insert(name, element).unwrap()
+        };
+        let stone = solid("Stone", 128, 20.0);
+        let sand = solid("Sand", 200, 35.0);
+        (world, stone, sand)
+    }
+
+    #[test]
+    fn edits_apply_in_order() {
+        let (mut world, stone, sand) = world();
+
+        let mut batch = EditBatch::default();
+        batch.fill_box(UVec3::new(0, 0, 0), UVec3::new(3, 0, 3), stone);
+        batch.set_atom(UVec3::new(20, 0, 0), stone);
+        batch.replace_element(stone, sand);
+        batch.set_atom(UVec3::new(1, 0, 1), Element::AIR_ID);
+        let applied = apply_edits(&mut world, batch.edits);
+
+        assert_eq!(applied.edits, 4);
+        assert_eq!(
+            applied.dirty_chunks,
+            HashSet::from_iter([UVec3::ZERO, UVec3::new(16, 0, 0)])
+        );
+        assert_eq!(world.atoms[UVec3::new(0, 0, 0)].element, sand);
+        assert_eq!(world.atoms[UVec3::new(20, 0, 0)].element, sand);
+        assert_eq!(world.atoms[UVec3::new(1, 0, 1)].element, Element::AIR_ID);
+
+        let applied = apply_edits(&mut world, [Edit::Clear]);
+        assert_eq!(
+            applied.dirty_chunks,
+            HashSet::from_iter([UVec3::ZERO, UVec3::new(16, 0, 0)])
+        );
+        assert_eq!(world.atoms[UVec3::new(0, 0, 0)].element, Element::AIR_ID);
+    }
+
+    #[test]
+    fn fill_box_is_clipped_to_world() {
+        let (mut world, _, sand) = world();
+        let corner = DEFAULT_SIZE - UVec3::splat(2);
+        let mut batch = EditBatch::default();
+        batch.fill_box(corner, DEFAULT_SIZE + UVec3::splat(20), sand);
+        let applied = apply_edits(&mut world, batch.edits);
+
+        assert_eq!(
+            applied.dirty_chunks,
+            HashSet::from_iter([DEFAULT_SIZE - UVec3::splat(16)])
+        );
+        for pos in [corner, DEFAULT_SIZE - UVec3::ONE] {
+            assert_eq!(world.atoms[pos].element, sand);
+            assert_eq!(world.atoms.temperature(pos), 35.0);
+        }
+        assert_eq!(world.atoms[corner - UVec3::X].element, Element::AIR_ID);
+    }
+
+    #[test]
+    fn replace_air_fills_unstored_chunks() {
+        let (mut world, stone, sand) = world();
+        world.atoms = Atoms::new(UVec3::new(32, 16, 16));
+        let mut batch = EditBatch::default();
+        batch.set_atom(UVec3::ZERO, stone);
+        batch.replace_element(Element::AIR_ID, sand);
+        let applied = apply_edits(&mut world, batch.edits);
+
+        assert_eq!(
+            applied.dirty_chunks,
+            HashSet::from_iter([UVec3::ZERO, UVec3::new(16, 0, 0)])
+        );
+        assert_eq!(world.atoms[UVec3::ZERO].element, stone);
+        assert_eq!(world.atoms[UVec3::new(1, 0, 0)].element, sand);
+        assert_eq!(world.atoms[UVec3::new(31, 15, 15)].element, sand);
+        assert_eq!(world.atoms.temperature(UVec3::new(31, 15, 15)), 35.0);
+        assert!(world.atoms.is_awake(UVec3::new(16, 0, 0)));
+    }
+}
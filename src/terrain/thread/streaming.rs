@@ -0,0 +1,428 @@
+//! Unloading chunks far from the player to disk, and loading them back once
+//! the player comes near again.  This keeps memory down in big worlds, but
+//! the world is still bounded by its size.
+//!
+//! Chunks are written with [`save::write_chunk`], so they store the names of
+//! their elements and survive the set being reloaded while they're unloaded.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process,
+};
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::terrain::{
+    rendering::CHUNK_SIZE,
+    save::{self, LoadSummary},
+    AtomWorld,
+};
+
+use super::edit::Edit;
+
+/// Unloaded chunks this close to the player, measured in chunks, are loaded.
+pub const LOAD_DISTANCE: u32 = 8;
+
+/// Chunks further than this from the player, measured in chunks, are
+/// unloaded.  Bigger than [`LOAD_DISTANCE`] so chunks on the edge aren't
+/// loaded and unloaded over and over as the player moves back and forth.
+pub const UNLOAD_DISTANCE: u32 = 12;
+
+/// Chunks this close to an awake chunk, measured in chunks, are kept loaded,
+/// and loaded again before the next tick if they were unloaded.
+/// Simulating a chunk also simulates its neighbours, which can move atoms
+/// one chunk further, so unloading these would lose atoms.
+const SIMULATION_MARGIN: u32 = 2;
+
+/// Chunks are loaded and unloaded at least once every this many updates, as
+/// well as whenever the player moves to another chunk.
+const UPDATE_INTERVAL: u32 = 64;
+
+/// The chunks that were unloaded to disk.
+#[derive(Debug)]
+pub struct ChunkStore {
+    dir: PathBuf,
+    /// Positions of the unloaded chunks, measured in atoms.
+    unloaded: HashSet<UVec3>,
+    /// Where the player is, measured in chunks.  Nothing is unloaded until
+    /// this is known.
+    focus: Option<UVec3>,
+    /// Where the player was the last time chunks were loaded and unloaded.
+    updated_focus: Option<UVec3>,
+    /// Updates since chunks were last loaded and unloaded.
+    since_update: u32,
+}
+
+impl ChunkStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            unloaded: HashSet::default(),
+            focus: None,
+            updated_focus: None,
+            since_update: 0,
+        }
+    }
+
+    /// Stores chunks in a directory for this process in the system's
+    /// temporary directory.
+    pub fn in_temp_dir() -> Self {
+        Self::new(env::temp_dir().join(format!("particle_sim-{}", process::id())))
+    }
+
+    /// Sets where the player is, measured in atoms.
+    pub fn set_focus(&mut self, pos: UVec3) {
+        self.focus = Some(pos / CHUNK_SIZE as u32);
+    }
+
+    /// Loads unloaded chunks near anything being simulated, so atoms aren't
+    /// moved into a chunk that's still on disk.  Also unloads chunks that are
+    /// far from the player and loads unloaded chunks near the player, but
+    /// only when the player moved to another chunk or every
+    /// [`UPDATE_INTERVAL`] calls.  Called every tick.
+    pub fn update(&mut self, world: &mut AtomWorld) {
+        let needed: Vec<_> = self
+            .unloaded
+            .iter()
+            .copied()
+            .filter(|&pos| near_awake(world, pos))
+            .collect();
+        for pos in needed {
+            self.load(world, pos);
+        }
+
+        self.since_update += 1;
+        if self.focus == self.updated_focus && self.since_update < UPDATE_INTERVAL {
+            return;
+        }
+        self.updated_focus = self.focus;
+        self.since_update = 0;
+
+        let near_focus = |store: &Self, pos: UVec3, distance: u32| {
+            store
+                .focus
+                .is_none_or(|focus| chunk_distance(focus, pos / CHUNK_SIZE as u32) <= distance)
+        };
+
+        let far: Vec<_> = world
+            .atoms
+            .chunk_positions()
+            .filter(|&pos| !near_focus(self, pos, UNLOAD_DISTANCE) && !near_awake(world, pos))
+            .collect();
+        for pos in far {
+            self.unload(world, pos);
+        }
+
+        let near: Vec<_> = self
+            .unloaded
+            .iter()
+            .copied()
+            .filter(|&pos| near_focus(self, pos, LOAD_DISTANCE))
+            .collect();
+        for pos in near {
+            self.load(world, pos);
+        }
+    }
+
+    /// Loads the chunks that `edits` could change, so they aren't edited while
+    /// their atoms are on disk.  Returns whether they could all be loaded.
+    #[must_use]
+    pub fn prepare_edits(&mut self, world: &mut AtomWorld, edits: &[Edit]) -> bool {
+        let mut loaded = true;
+        for edit in edits {
+            let (min, max) = match *edit {
+                Edit::SetAtom { pos, .. } => (pos, pos),
+                Edit::FillBox { min, max, .. } => (min, max),
+                Edit::ReplaceElement { .. } | Edit::Clear => return self.load_all(world),
+            };
+            let (min, max) = (min / CHUNK_SIZE as u32, max / CHUNK_SIZE as u32);
+            let touched: Vec<_> = self
+                .unloaded
+                .iter()
+                .copied()
+                .filter(|&pos| {
+                    let chunk_pos = pos / CHUNK_SIZE as u32;
+                    chunk_pos.cmpge(min).all() && chunk_pos.cmple(max).all()
+                })
+                .collect();
+            for pos in touched {
+                loaded &= self.load(world, pos);
+            }
+        }
+        loaded
+    }
+
+    /// Loads every unloaded chunk.  Returns whether they could all be loaded.
+    #[must_use]
+    pub fn load_all(&mut self, world: &mut AtomWorld) -> bool {
+        let unloaded: Vec<_> = self.unloaded.iter().copied().collect();
+        let mut loaded = true;
+        for pos in unloaded {
+            loaded &= self.load(world, pos);
+        }
+        loaded
+    }
+
+    /// Forgets every unloaded chunk, such as when the world is replaced.
+    pub fn clear(&mut self) {
+        for pos in self.unloaded.drain() {
+            // Chunks are only read back if they're in `unloaded`, so a file
+            // that's left behind does no harm.
+            let _ = fs::remove_file(chunk_path(&self.dir, pos));
+        }
+    }
+
+    fn unload(&mut self, world: &mut AtomWorld, pos: UVec3) {
+        let path = chunk_path(&self.dir, pos);
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|()| File::create(&path))
+            .and_then(|file| save::write_chunk(world, pos, BufWriter::new(file)));
+        match result {
+            Ok(()) => {
+                world.atoms.remove_chunk(pos);
+                self.unloaded.insert(pos);
+            }
+            Err(e) => error!("Unable to unload chunk at {pos} to {}: {e}", path.display()),
+        }
+    }
+
+    /// Reads an unloaded chunk back into the world.  If it can't be read, it
+    /// stays unloaded and its file is kept, so loading it can be tried again.
+    fn load(&mut self, world: &mut AtomWorld, pos: UVec3) -> bool {
+        let path = chunk_path(&self.dir, pos);
+        let mut summary = LoadSummary::default();
+        let result = File::open(&path)
+            .map_err(save::LoadError::from)
+            .and_then(|file| save::read_chunk(&world.elements, BufReader::new(file), &mut summary));
+        let chunk = match result {
+            Ok(chunk) if chunk.pos == pos => chunk,
+            Ok(chunk) => {
+                error!(
+                    "Chunk file {} is for the chunk at {}, not {pos}",
+                    path.display(),
+                    chunk.pos
+                );
+                return false;
+            }
+            Err(e) => {
+                error!("Unable to load chunk at {pos} from {}: {e}", path.display());
+                return false;
+            }
+        };
+        chunk.insert_into(&mut world.atoms);
+        self.unloaded.remove(&pos);
+        for name in summary.missing_elements {
+            warn!("Element {name} isn't in the current set; replaced it with air");
+        }
+        let _ = fs::remove_file(path);
+        true
+    }
+}
+
+impl Drop for ChunkStore {
+    fn drop(&mut self) {
+        self.clear();
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+fn chunk_path(dir: &Path, pos: UVec3) -> PathBuf {
+    let pos = pos / CHUNK_SIZE as u32;
+    dir.join(format!("{}_{}_{}.chunk", pos.x, pos.y, pos.z))
+}
+
+/// Whether the chunk at `pos`, measured in atoms, is within
+/// [`SIMULATION_MARGIN`] chunks of an awake chunk.
+fn near_awake(world: &AtomWorld, pos: UVec3) -> bool {
+    let chunk_pos = (pos / CHUNK_SIZE as u32).as_ivec3();
+    let margin = SIMULATION_MARGIN as i32;
+    (-margin..=margin).any(|x| {
+        (-margin..=margin).any(|y| {
+            (-margin..=margin).any(|z| {
+                let neighbour = chunk_pos + IVec3 { x, y, z };
+                neighbour.cmpge(IVec3::ZERO).all()
+                    && world
+                        .atoms
+                        .is_awake(neighbour.as_uvec3() * CHUNK_SIZE as u32)
+            })
+        })
+    })
+}
+
+/// Distance between two chunks, measured in chunks, along the axis they're
+/// furthest apart on.
+fn chunk_distance(a: UVec3, b: UVec3) -> u32 {
+    (a.as_ivec3() - b.as_ivec3()).abs().max_element() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        atom_physics::{
+            element::{Element, State},
+            id::MappedToId,
+            reaction::Reactions,
+        },
+        terrain::{color::AtomColor, simulation, storage::Atoms, Atom},
+    };
+
+    use super::*;
+
+    const FAR: UVec3 = UVec3::new(800, 3, 4);
+
+    fn store(name: &str) -> ChunkStore {
+        ChunkStore::new(env::temp_dir().join(format!("particle_sim-{name}-{}", process::id())))
+    }
+
+    /// A long world with a stone atom at [`FAR`], which is asleep unless
+    /// `awake`.
+    fn world(awake: bool) -> (AtomWorld, Atom) {
+        let mut world = AtomWorld {
+            atoms: Atoms::new(UVec3::new(1024, 16, 16)),
+            elements: Element::create_map(),
+            reactions: Reactions::default(),
+            seed: 0,
+            tick: 0,
+        };
+        let element = Element {
+            color: AtomColor::from_grey(128),
+            ..Default::default()
+        };
+        let id = world.elements.insert("Stone", element).unwrap();
+        let stone = world.elements.instance_of(id).unwrap();
+        world.atoms.set(FAR, stone.clone());
+        world.atoms.set_temperature(FAR, 90.0);
+        if !awake {
+            while world.atoms.is_awake(FAR) {
+                world.atoms.active_chunks();
+            }
+        }
+        world.atoms.take_changed_chunks();
+        (world, stone)
+    }
+
+    #[test]
+    fn far_chunks_round_trip() {
+        let mut store = store("round-trip");
+        let (mut world, stone) = world(false);
+
+        store.set_focus(UVec3::ZERO);
+        store.update(&mut world);
+        assert_eq!(world.atoms.chunk_positions().count(), 0);
+        assert_eq!(world.atoms[FAR], Atom::AIR);
+        assert_eq!(world.atoms.take_removed_chunks(), [UVec3::new(800, 0, 0)]);
+
+        store.set_focus(FAR);
+        store.update(&mut world);
+        assert_eq!(world.atoms[FAR], stone);
+        assert_eq!(world.atoms.temperature(FAR), 90.0);
+        assert_eq!(world.atoms.take_changed_chunks(), [UVec3::new(800, 0, 0)]);
+    }
+
+    #[test]
+    fn unreadable_chunks_stay_unloaded() {
+        let mut store = store("unreadable");
+        let (mut world, stone) = world(false);
+        store.set_focus(UVec3::ZERO);
+        store.update(&mut world);
+
+        let path = chunk_path(&store.dir, UVec3::new(800, 0, 0));
+        let contents = fs::read(&path).unwrap();
+        fs::write(&path, b"not a chunk").unwrap();
+        assert!(!store.load_all(&mut world));
+        assert_eq!(world.atoms[FAR], Atom::AIR);
+        assert!(path.exists());
+
+        fs::write(&path, contents).unwrap();
+        assert!(store.load_all(&mut world));
+        assert_eq!(world.atoms[FAR], stone);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn updates_are_throttled() {
+        let mut store = store("throttled");
+        let (mut world, stone) = world(false);
+        store.set_focus(UVec3::ZERO);
+        store.update(&mut world);
+        assert_eq!(world.atoms[FAR], Atom::AIR);
+
+        assert!(store.load_all(&mut world));
+        while world.atoms.is_awake(FAR) {
+            world.atoms.active_chunks();
+        }
+        // The player hasn't moved to another chunk, so the chunk stays until
+        // the next periodic update.
+        store.set_focus(UVec3::X);
+        for _ in 1..UPDATE_INTERVAL {
+            store.update(&mut world);
+            assert_eq!(world.atoms[FAR], stone);
+        }
+        store.update(&mut world);
+        assert_eq!(world.atoms[FAR], Atom::AIR);
+    }
+
+    #[test]
+    fn awake_chunks_stay_loaded() {
+        let mut store = store("awake");
+        let (mut world, stone) = world(true);
+        store.set_focus(UVec3::ZERO);
+        store.update(&mut world);
+        assert_eq!(world.atoms[FAR], stone);
+    }
+
+    #[test]
+    fn edited_chunks_are_loaded() {
+        let mut store = store("edited");
+        let (mut world, stone) = world(false);
+        store.set_focus(UVec3::ZERO);
+        store.update(&mut world);
+        assert_eq!(world.atoms[FAR], Atom::AIR);
+
+        let edits = [Edit::FillBox {
+            min: FAR - UVec3::X,
+            max: FAR + UVec3::X,
+            element: Element::AIR_ID,
+        }];
+        assert!(store.prepare_edits(&mut world, &edits));
+        assert_eq!(world.atoms[FAR], stone);
+    }
+
+    #[test]
+    fn falling_atoms_load_chunks_below() {
+        let mut store = store("falling");
+        let (mut world, stone) = world(false);
+        world.atoms = Atoms::new(UVec3::new(16, 256, 16));
+        for x in 0..16 {
+            for z in 0..16 {
+                world.atoms.set(UVec3::new(x, 3, z), stone.clone());
+            }
+        }
+        let element = Element {
+            state: State::Powder,
+            density: State::Powder.default_density(),
+            ..Default::default()
+        };
+        let id = world.elements.insert("Sand", element).unwrap();
+        let sand = world.elements.instance_of(id).unwrap();
+        while world.atoms.is_awake(UVec3::ZERO) {
+            world.atoms.active_chunks();
+        }
+        world.atoms.set(UVec3::new(4, 56, 4), sand.clone());
+
+        // The stone's chunk is too far from the player and the sand to stay
+        // loaded, but the sand reaches it long before the next periodic update.
+        store.set_focus(UVec3::new(0, 255, 0));
+        store.update(&mut world);
+        assert_eq!(world.atoms[UVec3::new(4, 3, 4)], Atom::AIR);
+        for _ in 0..60 {
+            simulation::step(&mut world);
+            store.update(&mut world);
+        }
+        assert_eq!(world.atoms[UVec3::new(4, 3, 4)], stone);
+        assert_eq!(world.atoms[UVec3::new(4, 4, 4)], sand);
+    }
+}